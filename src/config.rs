@@ -8,11 +8,28 @@ use std::error::Error;
 use std::fs::File;
 use std::path::Path;
 
+use crate::world::StorageKind;
+
+fn default_max_packet_size() -> usize { crate::server::packet::DEFAULT_MAX_PACKET_SIZE }
+fn default_max_players() -> usize { 16 }
+fn default_autosave_interval() -> u64 { 300 }
+fn default_backups() -> usize { 5 }
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Config {
 	pub addr: String,
 	pub port: u16,
 	pub world: String,
+	#[serde(default = "default_max_packet_size")]
+	pub max_packet_size: usize, // Bytes, clients sending larger packets are disconnected
+	#[serde(default = "default_max_players")]
+	pub max_players: usize, // Clients joining a full server are disconnected
+	#[serde(default = "default_autosave_interval")]
+	pub autosave_interval: u64, // Seconds between autosaves of modified chunks (0 to disable)
+	#[serde(default = "default_backups")]
+	pub backups: usize, // Previous versions of the world file kept when saving
+	#[serde(default)]
+	pub storage: StorageKind, // Where the hosted world is saved (directory, sqlite or memory)
 	#[serde(skip_serializing, skip_deserializing)]
 	pub from_file: bool,
 }
@@ -23,6 +40,11 @@ impl Config {
 			addr: addr.to_owned(),
 			port: port,
 			world: world.to_owned(),
+			max_packet_size: default_max_packet_size(),
+			max_players: default_max_players(),
+			autosave_interval: default_autosave_interval(),
+			backups: default_backups(),
+			storage: StorageKind::default(),
 			from_file: false,
 		}
 	}
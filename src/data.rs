@@ -1,10 +1,130 @@
 // File for loading various data
 
+use std::{
+	error::Error,
+	collections::HashMap,
+	sync::OnceLock,
+};
+use serde::Deserialize;
+
 // Load tile attributes
-static TILE: &Yaml = load_yaml!("data/tile.yml");
+const TILE_YAML: &str = include_str!("data/tile.yml");
+
+/// Attributes of a tile id (from data/tile.yml)
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TileInfo {
+	pub alch: bool, // Alchemy herb
+	pub axe: bool, // Broken with an axe
+	pub blend_all: bool,
+	pub block_light: bool,
+	pub bouncy: bool,
+	pub brick: bool,
+	pub container: bool, // Chests & dressers
+	pub cracked: bool,
+	pub cut: bool, // Broken by swinging weapons & projectiles
+	pub dungeon: bool,
+	pub flame: bool,
+	pub frame_important: bool, // Frame data is saved & sent with the tile
+	pub glow_mask: i16, // -1 if the tile has none
+	pub hammer: bool, // Broken with a hammer
+	pub large_frames: u8,
+	pub lava_death: bool, // Destroyed by lava
+	pub lighted: bool,
+	pub merge_dirt: bool,
+	pub moss: bool,
+	pub no_attach: bool,
+	pub no_fail: bool,
+	pub no_sun_light: bool,
+	pub obsidian_kill: bool,
+	pub ore_finder_priority: i16, // Metal detector priority
+	pub pile: bool,
+	pub rope: bool,
+	pub sand: bool, // Falls when nothing is below it
+	pub shine: u16,
+	pub shine2: bool,
+	pub sign: bool,
+	pub solid: bool,
+	pub solid_top: bool, // Platforms, tables...
+	pub spelunker: bool,
+	pub stone: bool,
+	pub table: bool,
+	pub water_death: bool, // Destroyed by water
+}
+
+/// Tile attributes indexed by tile id
+#[derive(Debug)]
+pub struct TileRegistry {
+	tiles: Vec<TileInfo>,
+}
+impl TileRegistry {
+	/// Parse tile.yml, which lists tiles as `tile<id>` entries in order of id
+	pub fn from_yaml(yaml: &str) -> Result<TileRegistry, Box<dyn Error>> {
+		#[derive(Deserialize)]
+		struct TileFile { tiles: Vec<HashMap<String, TileInfo>> }
+
+		let file: TileFile = serde_yaml::from_str(yaml)?;
+		let mut tiles = Vec::with_capacity(file.tiles.len());
+		for (id, entry) in file.tiles.into_iter().enumerate() {
+			let (name, info) = entry.into_iter().next().ok_or_else(|| format!("Tile entry {} is empty", id))?;
+			if name != format!("tile{}", id) { return Err(format!("Expected entry tile{}, found {}", id, name).into()) }
+			tiles.push(info);
+		}
+		Ok(TileRegistry { tiles })
+	}
+	pub fn get(&self, id: u16) -> Option<&TileInfo> {
+		self.tiles.get(id as usize)
+	}
+	/// False for unknown ids
+	pub fn is_frame_important(&self, id: u16) -> bool {
+		self.get(id).is_some_and(|info| info.frame_important)
+	}
+	/// Frame importance of every tile id (like `World::tile_frame_important`)
+	pub fn frame_important(&self) -> Vec<bool> {
+		self.tiles.iter().map(|info| info.frame_important).collect()
+	}
+}
+
+/// Tile attributes from data/tile.yml (parsed on first use)
+pub fn tiles() -> &'static TileRegistry {
+	static TILES: OnceLock<TileRegistry> = OnceLock::new();
+	TILES.get_or_init(|| TileRegistry::from_yaml(TILE_YAML).expect("Invalid data/tile.yml"))
+}
 
 // TODO: NPC data
 
 // TODO: Wall data
 
 // TODO: Item data
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tile_registry_covers_vanilla_ids() {
+		let tiles = tiles();
+		assert_eq!(tiles.frame_important().len(), crate::world::vanilla::TILE_COUNT);
+		assert!(tiles.get(crate::world::vanilla::TILE_COUNT as u16).is_none());
+		assert!(tiles.is_frame_important(36) && !tiles.is_frame_important(0));
+		assert_eq!(tiles.frame_important().iter().filter(|&&important| important).count(), 127);
+	}
+	#[test]
+	fn tile_type_matches_registry() {
+		use crate::enum_primitive::FromPrimitive;
+		use crate::world::chunk::TileType;
+		let count = tiles().frame_important().len() as u16;
+		assert!((0..count).all(|id| TileType::from_u16(id).is_some()));
+		assert!(TileType::from_u16(count).is_none());
+		assert!(tiles().get(TileType::Containers as u16).unwrap().container);
+		assert!(tiles().get(TileType::Rope as u16).unwrap().rope);
+		assert_eq!(TileType::Platinum as u16, 169);
+	}
+	#[test]
+	fn tile_entries_have_to_be_in_order() {
+		let yaml = "tiles:\n  - tile1:\n      solid: true\n";
+		assert!(TileRegistry::from_yaml(yaml).is_err());
+		let registry = TileRegistry::from_yaml("tiles:\n  - tile0:\n      solid: true\n").unwrap();
+		assert!(registry.get(0).unwrap().solid);
+	}
+}
@@ -8,21 +8,56 @@ extern crate env_logger;
 use clap::App;
 use log::LevelFilter;
 use env_logger::Builder;
-use std::{sync::Arc, fs::File};
+use std::{sync::Arc, fs::File, io::{BufReader, BufWriter, Write}};
 use tokio::sync::Mutex;
+use tokio::signal;
 use log::{info, error};
+use indicatif::{ProgressBar, ProgressStyle};
 
 // Config loading & saving
 
 mod config;
 use config::Config;
 
+mod data;
+
 mod world;
-use world::World;
+use world::{World, WorldStorage};
 
 mod server;
 use server::*;
 
+/// Resolves when the process receives SIGINT (or SIGTERM on unix)
+async fn shutdown_signal() {
+	#[cfg(unix)]
+	{
+		use signal::unix::{signal, SignalKind};
+		let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+		tokio::select! {
+			_ = signal::ctrl_c() => {},
+			_ = terminate.recv() => {},
+		}
+	}
+	#[cfg(not(unix))]
+	{ let _ = signal::ctrl_c().await; }
+}
+
+/// Add every chunk in storage to the world
+fn load_chunks(world: &mut World, storage: &dyn WorldStorage) {
+	for coord in storage.list_chunks().expect("Failed to list chunks") {
+		if let Some(chunk) = storage.load_chunk(&coord).expect("Failed to read chunk") {
+			world.chunks.insert(coord, chunk);
+		}
+	}
+}
+
+/// Terminal progress bar for conversions
+fn progress_bar() -> ProgressBar {
+	let bar = ProgressBar::new(0);
+	bar.set_style(ProgressStyle::default_bar().template("{msg:>12} [{bar:40}] {percent:>3}% ({pos}/{len})"));
+	bar
+}
+
 #[tokio::main]
 async fn main() {
 	let mut builder = Builder::new();
@@ -32,23 +67,6 @@ async fn main() {
 	let yaml_args = load_yaml!("app.yml");
 	let matches = App::from_yaml(yaml_args).get_matches();
 	
-	if let Some(matches) = matches.subcommand_matches("convert") {
-		// TODO: progress bars
-		let input_file = matches.value_of("input").expect("Please specify input file with --input or -i");
-		let output_file = matches.value_of("output").expect("Please specify output file with --output or -o");
-		
-		let mut input = File::open(input_file).expect(&format!("Unable to read input file: {}", input_file)[..]);
-		info!("Reading Vanilla World: {}", input_file);
-		let world = world::vanilla::read(&mut input).expect("Failed to parse vanilla world");
-		
-		let mut output = File::create(output_file).expect(&format!("Unable to create output file: {}. Are the permissions wrong?", output_file));
-		info!("Writing Terraria World: {}", output_file);	
-		world.write(&mut output).expect("Failed to Output Wirkd");
-		
-		info!("Finished!");
-		return ();
-	}
-
 	use std::path::Path;
 	
 	let mut config = Config::new("127.0.0.1", 7777, "world.twld"); // Default port 7777, default world file name "world.wld" (in CWD)
@@ -72,22 +90,106 @@ async fn main() {
 		config.port = port_str.parse().expect("Error, port value not correct");
 	}
 
+	if let Some(matches) = matches.subcommand_matches("convert") {
+		let input_file = matches.value_of("input").expect("Please specify input file with --input or -i");
+		let output_file = matches.value_of("output").expect("Please specify output file with --output or -o");
+		
+		let open_input = || File::open(input_file).unwrap_or_else(|err| panic!("Unable to read input file {}: {}", input_file, err));
+		if matches.is_present("to-vanilla") {
+			info!("Reading Terrarium World: {}", input_file);
+			// Read from the configured storage, falling back to the world file if it has no metadata
+			let storage = world::storage::open(config.storage, input_file, 0).expect("Could not open world storage");
+			let mut world = match storage.load_world().expect("Failed to read terrarium world") {
+				Some(world) => world,
+				None => World::read(&mut BufReader::new(open_input())).expect("Failed to read terrarium world"),
+			};
+			load_chunks(&mut world, &*storage);
+			
+			info!("Writing Vanilla World: {}", output_file);
+			let mut output = BufWriter::new(File::create(output_file).unwrap_or_else(|err| panic!("Unable to create output file {}: {}", output_file, err)));
+			world::vanilla::write(&world, &world.chunks, &mut output).expect("Failed to Output World");
+			output.flush().expect("Failed to Output World");
+		} else {
+			info!("Reading Vanilla World: {}", input_file);
+			let bar = progress_bar();
+			let mut world = world::vanilla::read_with_progress(&mut BufReader::new(open_input()), |stage, done, total| {
+				if done == 1 { bar.set_message(&stage.to_string()); bar.set_length(total); }
+				bar.set_position(done);
+			}).expect("Failed to parse vanilla world");
+			
+			info!("Writing Terrarium World: {}", output_file);
+			if matches.is_present("regions") {
+				// Chunks go to region files next to the world file, the same way hosted worlds are stored
+				let regions = world::storage::RegionStorage::dir_for_world(output_file);
+				if regions.exists() { // Chunks of a world that was converted to the same file before
+					std::fs::remove_dir_all(&regions).expect("Failed to clear region files");
+				}
+				let storage = world::storage::DirectoryStorage::new(output_file, 0);
+				bar.set_message("Compressing");
+				bar.set_length(world.chunks.len() as u64);
+				world.move_chunks_to(&storage, |done, _| bar.set_position(done)).expect("Failed to Output World");
+				bar.finish_and_clear();
+				storage.save_world(&world).expect("Failed to Output World");
+			} else {
+				bar.finish_and_clear();
+				world.save(output_file, 0).expect("Failed to Output World");
+			}
+		}
+		
+		info!("Finished!");
+		return ();
+	}
+
 	//println!("{:#?}", config);
+	
+	// Open world storage, importing the world file if storage is empty
+	let storage = world::storage::open(config.storage, &config.world, config.backups).expect("Could not open world storage");
 
-	// Read world file
-	let mut world_file = File::open(&config.world).expect("Could not find terrarium world file");
+	if let Some(matches) = matches.subcommand_matches("restore") {
+		let backups = storage.backups().expect("Failed to read backup directory");
+		if let Some(selected) = matches.value_of("backup") {
+			// Either index into list or timestamp of backup
+			let backup = selected.parse::<u64>().ok().and_then(|num| {
+				backups.iter().find(|(time, _)| *time == num).or_else(|| backups.get(num as usize))
+			}).unwrap_or_else(|| panic!("No backup matches {}", selected));
+			
+			storage.restore(&backup.1).expect("Failed to restore backup");
+		} else {
+			if backups.is_empty() { println!("No backups of {}", config.world); }
+			let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+			for (i, (time, path)) in backups.iter().enumerate() {
+				println!("[{}] {} ({} minutes ago)", i, path.display(), now.saturating_sub(*time) / 60);
+			}
+		}
+		return;
+	}
 
 	info!("Loading World: {}", config.world);
 	
-	let world = World::read(&mut world_file).expect("Could not read world");
+	let mut world = match storage.load_world().expect("Could not read world") {
+		Some(world) => world,
+		None => {
+			let mut world_file = File::open(&config.world).expect("Could not find terrarium world file");
+			let mut world = World::read(&mut world_file).expect("Could not read world");
+			if world.chunks.is_empty() && config.storage != world::StorageKind::Directory { // Converted worlds keep their chunks in region files
+				load_chunks(&mut world, &world::storage::DirectoryStorage::new(&config.world, 0));
+			}
+			world
+		},
+	};
+	if !world.chunks.is_empty() { // Stored metadata doesn't include chunks
+		let count = world.move_chunks_to(&*storage, |_, _| {}).expect("Failed to move chunks to storage");
+		storage.save_world(&world).expect("Failed to save world");
+		info!("Moved {} chunks to {:?} storage", count, config.storage);
+	}
 	let world = Arc::new(Mutex::new(world));
 	
-	let server = Server::new(&config.get_address());
+	let server = Server::new(&config);
 	let server = Arc::new(Mutex::new(server));
 	
-	// TODO: SIGINT/SIGTERM catching to gracefullly shutdown server (and save world)
-	let result = Server::serve(server.clone(), world.clone()).await; // Run Server
+	let result = Server::serve(server.clone(), world.clone(), storage.clone(), shutdown_signal()).await; // Run Server until SIGINT/SIGTERM
 	if let Err(err) = result { error!("Server Crashed: {:?}", err); }
 	
-	//world.write(&mut world_file).expect("Failed to save world");
+	info!("Saving World: {}", config.world);
+	storage.save_world(&*world.lock().await).expect("Failed to save world");
 }
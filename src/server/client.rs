@@ -22,46 +22,58 @@ use crate::server::{
 	},
 	ServerActionSender,
 	ServerAction,
+	CHAT_COLOR,
 	player::*,
 };
 use crate::world::*;
 
 #[derive(Debug)]
 pub enum ClientAction {
+	RequestSlot, // Client sent correct version, ask server for a player slot
 	SetClientID(usize),
 	SetPlayerName(String),
 	SetPlayerAppearance(Appearance),
 	SetPlayerUUID(String),
 	SetPlayerStat(Packet),
+	UpdatePlayerMovement(Movement),
+	SendChat(String, String), // Chat command & text
 	UpdateInventorySlot(Packet),
 	
 	RequestWorldInfo(),
-	RequestEssentialTiles(TileCoord),
+	RequestEssentialTiles(Option<TileCoord>), // Area around spawn point (world spawn if None)
+	ModifyTile(TileCoord, TileEdit),
+	SectionsSent, // Chunk thread finished sending requested sections
+	SpawnPlayer(Option<TileCoord>, u8), // Player spawned (at world spawn if None) & spawn context
+	AnnouncePlayer(ClientActionSender), // Send this client's player state to a newly joined client
 	
 	SendPacket(Packet),
-	UpdateChunkHandler(ChunkActionSender)
+	UpdateChunkHandler(ChunkActionSender),
+	Kick(NetworkText), // Disconnect client with reason
+	ConnectionClosed, // Client closed the connection
 }
 pub type ClientActionSender = mpsc::Sender<ClientAction>;
 pub struct Client {
 	pub id: usize, // What the client thinks its index is
 	pub player: Player,
 	pub action: ClientActionSender,
+	max_packet_size: usize,
 	connected_server: bool,
 	connected_world: bool,
 }
 impl Client {
-	pub fn new() -> (Self, mpsc::Receiver<ClientAction>) {
+	pub fn new(max_packet_size: usize) -> (Self, mpsc::Receiver<ClientAction>) {
 		let (action, action_receiver) = mpsc::channel(100);
 		(Client {
 			id: 0,
 			player: Player::default(),
 			action, 
+			max_packet_size,
 			connected_server: false,
 			connected_world: false
 		}, action_receiver)
 	}
-	pub async fn handle_packets(mut action: ClientActionSender, reader: ReadHalf<TcpStream>) -> Result<(), Box<dyn Error>> {
-		let mut packet_reader = FramedRead::new(reader, PacketCodec::default());
+	pub async fn handle_packets(mut action: ClientActionSender, reader: ReadHalf<TcpStream>, max_packet_size: usize) -> Result<(), Box<dyn Error>> {
+		let mut packet_reader = FramedRead::new(reader, PacketCodec::new(max_packet_size));
 		
 		loop {
 			match packet_reader.try_next().await { 
@@ -71,12 +83,12 @@ impl Client {
 					match packet {
 						Packet::ConnectRequest(s) => {
 							if s == "Terraria230"{
-								action.send(SendPacket(Packet::SetUserSlot(0))).await? // Every client is always in user slot 0 (other players are dynamically set up to 256 user slots by server thread)
+								action.send(RequestSlot).await? // Server thread assigns the player slot
 							} else {
-								action.send(SendPacket(Packet::Disconnect(NetworkText::new("LegacyMultiplayer.4")))).await? // Send "Wrong Version" prompt
+								action.send(SendPacket(Packet::Disconnect(NetworkText::localized("LegacyMultiplayer.4")))).await? // Send "Wrong Version" prompt
 							}
 						},
-						Packet::PlayerInfo(name, appearance) => {
+						Packet::PlayerInfo(_, name, appearance) => {
 							// Make sure these attributes can't be changed mid-game
 							action.send(SetPlayerName(name)).await?;
 							action.send(SetPlayerAppearance(appearance)).await?;
@@ -86,14 +98,21 @@ impl Client {
 						Packet::PlayerInventorySlot{..} => action.send(UpdateInventorySlot(packet)).await?,
 						Packet::WorldDataRequest => action.send(RequestWorldInfo()).await?,
 						Packet::EssentialTilesRequest(x, y) => {
-							action.send(SendPacket(Packet::Status(15, NetworkText::new("LegacyInterface.44"), 0))).await?;
-							// Request cached WorldInfo data from world
-							action.send(RequestEssentialTiles( TileCoord{x: x as u16, y: y as u16} )).await?;
+							// Request sections around spawn from chunk thread
+							let coord = if x < 0 || y < 0 { None } else { Some(TileCoord{x: x as u16, y: y as u16}) };
+							action.send(RequestEssentialTiles(coord)).await?;
+						}
+						Packet::ChatMessage{command, text} => action.send(SendChat(command, text)).await?,
+						Packet::TileManipulation{coord, edit} => action.send(ModifyTile(coord, edit)).await?,
+						Packet::PlayerUpdate{movement, ..} => action.send(UpdatePlayerMovement(movement)).await?,
+						Packet::SpawnPlayer{x, y, context, ..} => {
+							let coord = if x < 0 || y < 0 { None } else { Some(TileCoord{x: x as u16, y: y as u16}) };
+							action.send(SpawnPlayer(coord, context)).await?;
 						}
 						_ => warn!("Unimplemented Packet"), 
 					}
 				},
-				Ok(None) => continue,
+				Ok(None) => break, // Connection closed
 				Err(err) => {
 					error!("Failed to parse packet: {:?}", err);
 					break;
@@ -101,6 +120,7 @@ impl Client {
 			}
 		}
 		
+		action.send(ClientAction::ConnectionClosed).await?;
 		Ok(())
 	}
 	pub async fn handle(
@@ -115,8 +135,9 @@ impl Client {
 		
 		// Packet Parsing Thread
 		let packet_action = self.action.clone();
+		let max_packet_size = self.max_packet_size;
 		tokio::spawn(async move {
-			if let Err(err) = Client::handle_packets(packet_action, reader).await {
+			if let Err(err) = Client::handle_packets(packet_action, reader, max_packet_size).await {
 				error!("Packet Reading Thread errored: {:?}", err);
 			}
 		});
@@ -130,29 +151,111 @@ impl Client {
 				
 				match action { // Parse action
 					SendPacket(packet) => packet_writer.send(&packet).await?,
+					RequestSlot => {
+						if self.connected_server { continue; }
+						server_action.send(ServerAction::ConnectClient(self.action.clone())).await?;
+					},
+					SetClientID(id) => {
+						// Server is notified of Client
+						self.id = id;
+						self.player.id = id as u8;
+						self.connected_server = true;
+						packet_writer.send(&Packet::SetUserSlot(id as u8)).await?;
+					},
 					SetPlayerName(name) => {
-						if self.connected_server { continue; } // TODO: Maybe log double sends?
+						if !self.connected_server || !self.player.name.is_empty() { continue; } // TODO: Maybe log double sends?
 						
 						self.player.name = name;
-						// Server is notified of Client
-						self.connected_server = true;
-						server_action.send(ServerAction::ConnectClient(self.player.name.clone(), self.action.clone())).await?;
+						server_action.send(ServerAction::SetClientName(self.id, self.player.name.clone())).await?;
 					},
-					SetClientID(id) => {
-						self.id = id;
-					}
-					UpdateInventorySlot(packet) => {
+					Kick(reason) => {
+						info!("Disconnecting client {} ({:?}): {:?}", self.id, self.player.name, reason);
+						packet_writer.send(&Packet::Disconnect(reason)).await?;
+						break;
+					},
+					ConnectionClosed => break,
+					UpdateInventorySlot(mut packet) => {
 						//TODO: Implement config flag to have server-side managed inventory (e.g. drop this action)
-						self.player.inventory.update_slot(packet)?;
+						self.player.inventory.update_slot(packet.clone())?;
+						// Other players only need to see equipment & held items, not bank contents
+						if let Packet::PlayerInventorySlot{slot_index: 0..=98, ..} = packet {
+							self.relay(&mut server_action, &mut packet).await?;
+						}
+					},
+					SetPlayerStat(mut packet) => {
+						if let Err(err) = self.player.status.update(packet.clone()) {
+							warn!("Invalid stat packet from client {}: {}", self.id, err);
+							continue;
+						}
+						self.relay(&mut server_action, &mut packet).await?;
+					},
+					SetPlayerAppearance(appearance) => {
+						self.player.appearance = appearance;
+						let mut packet = Packet::PlayerInfo(0, self.player.name.clone(), self.player.appearance.clone());
+						self.relay(&mut server_action, &mut packet).await?;
+					},
+					SendChat(command, text) => {
+						if !self.connected_server { continue; }
+						match command.as_str() {
+							"Say" => server_action.send(ServerAction::Chat(self.id, text)).await?,
+							"Emote" => {
+								let text = NetworkText::new(&format!("*{} {}", self.player.name, text));
+								server_action.send(ServerAction::SystemMessage(None, text, CHAT_COLOR)).await?;
+							},
+							_ => debug!("Unimplemented Chat Command: {:?}", command),
+						}
+					},
+					UpdatePlayerMovement(movement) => {
+						let last_position = self.player.position;
+						self.player.update_movement(movement.clone());
+						// Let other players see movement
+						if self.connected_world {
+							if self.player.position != last_position {
+								world_action.send(WorldAction::PlayerMoved(self.id, self.player.position)).await?;
+							}
+							let packet = Packet::PlayerUpdate{id: self.id as u8, movement};
+							server_action.send(ServerAction::Relay(self.id, packet)).await?;
+						}
 					},
-					SetPlayerAppearance(appearance) => self.player.appearance = appearance,
 					
 					RequestWorldInfo() => {
 						world_action.send(WorldAction::RequestWorldInfo(self.action.clone())).await?;
 					},
-					RequestEssentialTiles(_coord) => {
+					RequestEssentialTiles(coord) => {
+						if !self.connected_server { continue; }
+						world_action.send(WorldAction::RequestSections(self.id, self.action.clone(), coord)).await?;
+					},
+					ModifyTile(coord, edit) => {
+						if !self.connected_world { continue; }
 						let mut lock = chunk_action.lock().await;
-						lock.send(ChunkAction::RequestSections(self.action.clone())).await?;
+						lock.send(ChunkAction::ModifyBlock(self.id, self.action.clone(), coord, edit)).await?;
+					},
+					SectionsSent => {
+						// Essential tiles are loaded, let client spawn in
+						if !self.connected_world { packet_writer.send(&Packet::StartPlaying).await?; }
+					},
+					SpawnPlayer(coord, context) => {
+						if let Some(coord) = coord { self.player.position = coord; }
+						if !self.connected_world {
+							self.connected_world = true;
+							packet_writer.send(&Packet::FinishedConnecting).await?;
+							info!("{} has joined the world", self.player.name);
+							
+							// Show new player to everyone else, then get everyone else to show themselves
+							for packet in self.player.state_packets() {
+								server_action.send(ServerAction::Relay(self.id, packet)).await?;
+							}
+							server_action.send(ServerAction::PlayerJoined(self.id)).await?;
+						}
+						let (x, y) = coord.map_or((-1, -1), |coord| (coord.x as i16, coord.y as i16));
+						let mut packet = Packet::SpawnPlayer{id: 0, x, y, respawn_timer: 0, context};
+						self.relay(&mut server_action, &mut packet).await?;
+					},
+					AnnouncePlayer(mut chan) => {
+						if !self.connected_world { continue; }
+						for packet in self.player.state_packets() {
+							chan.send(SendPacket(packet)).await?;
+						}
 					},
 					
 					UpdateChunkHandler(handle) => { // Update chunk arcswap if needed
@@ -167,7 +270,18 @@ impl Client {
 			}
 		}
 		
-		server_action.send(ServerAction::DisconnectClient(self.player.name.clone())).await?;
+		if self.connected_server {
+			let _ = world_action.send(WorldAction::RemovePlayer(self.id)).await; // World thread may have shut down
+			server_action.send(ServerAction::DisconnectClient(self.id)).await?;
+		}
+		Ok(())
+	}
+	/// Relay a player packet (with this client's id) to other clients once in the world
+	async fn relay(&self, server_action: &mut ServerActionSender, packet: &mut Packet) -> Result<(), Box<dyn Error>> {
+		if self.connected_world {
+			packet.set_player_id(self.id as u8);
+			server_action.send(ServerAction::Relay(self.id, packet.clone())).await?;
+		}
 		Ok(())
 	}
 }
\ No newline at end of file
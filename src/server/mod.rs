@@ -5,17 +5,20 @@ use log::{trace, debug, info, warn, error};
 use std::{
 	error::Error,
 	sync::Arc,
-	collections::HashMap,
+	collections::{HashMap, hash_map::Entry},
 };
 
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{mpsc, RwLock, Mutex};
+use std::future::Future;
 use tokio::stream::{self, StreamExt};
 use tokio_util::codec::{FramedRead, FramedWrite};
 use futures::sink::SinkExt;
 
 use crate::packet::{Packet, PacketCodec, PacketError, types::NetworkText};
+use crate::server::player::Color;
 use crate::world::*;
+use crate::config::Config;
 
 pub mod packet;
 
@@ -26,24 +29,71 @@ pub use client::{Client, ClientAction, ClientActionSender};
 
 #[derive(Debug)]
 pub enum ServerAction {
-	ConnectClient(String, ClientActionSender), // Connect client thread to server action thread
-	DisconnectClient(String), // Disconnect client
+	ConnectClient(ClientActionSender), // Connect client thread to server action thread (assigns player slot)
+	SetClientName(usize, String), // Register player name of client with id
+	DisconnectClient(usize), // Disconnect client with id
 	Broadcast(Packet), // Broadcast to all clients
+	Relay(usize, Packet), // Send to all clients except the one with this id
+	PlayerJoined(usize), // Client with id entered the world, other clients send it their player state
 	
-	Chat(String),
+	Chat(usize, String), // Chat message from client with id
+	SystemMessage(Option<usize>, NetworkText, Color), // Message to one client (or all if None)
+	
+	Shutdown(NetworkText), // Disconnect all clients with reason & exit the server thread
 }
+
+/// Color of chat messages sent by players
+pub const CHAT_COLOR: Color = Color::new(255, 255, 255);
+/// Default color of messages sent by the server
+pub const SYSTEM_MESSAGE_COLOR: Color = Color::new(255, 240, 20);
+/// Author id clients show as the server
+const SYSTEM_AUTHOR: u8 = 255;
+/// Player slots usable by clients (slot 255 is reserved for the server)
+pub const MAX_PLAYER_SLOTS: usize = 255;
+
 pub type ServerActionSender = mpsc::Sender<ServerAction>;
 pub struct Server {
-	clients: HashMap<usize, ClientActionSender>, // Channels to tell clients to send data
+	clients: HashMap<usize, ClientActionSender>, // Channels to tell clients to send data (indexed by player slot)
 	names: HashMap<String, usize>,
 	addr: String, // Addr server is hosting on
+	max_packet_size: usize, // Largest packet accepted from clients
+	max_players: usize,
+	autosave_interval: u64, // Seconds between autosaves (0 to disable)
 }
 impl Server {
-	pub fn new(addr: &str) -> Self {
+	pub fn new(config: &Config) -> Self {
 		Server {
 			clients: HashMap::with_capacity(8),
 			names: HashMap::with_capacity(8),
-			addr: addr.into(),
+			addr: config.get_address(),
+			max_packet_size: config.max_packet_size,
+			max_players: config.max_players.min(MAX_PLAYER_SLOTS),
+			autosave_interval: config.autosave_interval,
+		}
+	}
+	/// Lowest player slot that isn't taken
+	fn free_slot(&self) -> Option<usize> {
+		(0..self.max_players).find(|id| !self.clients.contains_key(id))
+	}
+	/// Send packet to all clients (except one id)
+	async fn broadcast(&mut self, packet: &Packet, except: Option<usize>) {
+		for (id, chan) in self.clients.iter_mut().filter(|(id, _)| Some(**id) != except) {
+			if let Err(err) = chan.send(ClientAction::SendPacket(packet.clone())).await {
+				warn!("Failed to send packet to client {}: {:?}", id, err);
+			}
+		}
+	}
+	/// Send a message from the server to one client (or all if target is None)
+	pub async fn send_system_message(&mut self, target: Option<usize>, text: NetworkText, color: Color) {
+		let packet = Packet::ChatBroadcast{author: SYSTEM_AUTHOR, text, color};
+		match target {
+			Some(id) => match self.clients.get_mut(&id) {
+				Some(chan) => if let Err(err) = chan.send(ClientAction::SendPacket(packet)).await {
+					warn!("Failed to send message to client {}: {:?}", id, err);
+				},
+				None => warn!("Tried to send message to client {} that isn't connected", id),
+			},
+			None => self.broadcast(&packet, None).await,
 		}
 	}
 	async fn handle(&mut self, mut action_receiver: mpsc::Receiver<ServerAction>) -> Result<(), Box<dyn Error>> {
@@ -53,37 +103,83 @@ impl Server {
 			use ServerAction::*;
 			use std::convert::TryInto;
 			match action {
-				ConnectClient(name, mut chan) => {
-					let id = self.names.len();
-					chan.send(ClientAction::SetClientID(id)).await?;
-					self.names.insert(name, id);
-					self.clients.insert(id, chan);
+				ConnectClient(mut chan) => {
+					if let Some(id) = self.free_slot() {
+						if chan.send(ClientAction::SetClientID(id)).await.is_ok() {
+							self.clients.insert(id, chan);
+						}
+					} else {
+						let _ = chan.send(ClientAction::Kick(NetworkText::new("Server is full"))).await;
+					}
+				},
+				SetClientName(id, name) => {
+					match self.names.entry(name) {
+						Entry::Occupied(entry) => if let Some(chan) = self.clients.get_mut(&id) {
+							let _ = chan.send(ClientAction::Kick(NetworkText::new(&format!("{} is already on this server.", entry.key())))).await;
+						},
+						Entry::Vacant(entry) => { entry.insert(id); },
+					}
 				},
-				DisconnectClient(name) => {
-					if let Some(id) = self.names.remove(&name) {
-						self.clients.remove(&id);
+				DisconnectClient(id) => {
+					if self.clients.remove(&id).is_some() {
+						let name = self.names.iter().find(|(_, name_id)| **name_id == id).map(|(name, _)| name.clone());
+						self.names.retain(|_, name_id| *name_id != id);
+						
+						self.broadcast(&Packet::PlayerActive{id: id as u8, active: false}, None).await;
+						if let Some(name) = name {
+							self.send_system_message(None, NetworkText::new(&format!("{} has left.", name)), SYSTEM_MESSAGE_COLOR).await;
+						}
 					} else {
-						error!("Client {:?} allready left, but Disconnect ServerAction was called again", name);
+						error!("Client {:?} allready left, but Disconnect ServerAction was called again", id);
 					}
 				},
-				Chat(s) => info!("Received Chat {}", s),
-				_ => warn!("Unimplemented Action")
+				Broadcast(packet) => self.broadcast(&packet, None).await,
+				Relay(from, packet) => self.broadcast(&packet, Some(from)).await,
+				PlayerJoined(id) => {
+					let chan = match self.clients.get(&id) { Some(chan) => chan.clone(), None => continue };
+					for (other_id, other) in self.clients.iter_mut().filter(|(other_id, _)| **other_id != id) {
+						if let Err(err) = other.send(ClientAction::AnnouncePlayer(chan.clone())).await {
+							warn!("Failed to announce client {} to {}: {:?}", other_id, id, err);
+						}
+					}
+					if let Some((name, _)) = self.names.iter().find(|(_, name_id)| **name_id == id) {
+						let text = NetworkText::new(&format!("{} has joined.", name));
+						self.send_system_message(None, text, SYSTEM_MESSAGE_COLOR).await;
+					}
+				},
+				Chat(id, text) => {
+					let name = self.names.iter().find(|(_, client_id)| **client_id == id).map(|(name, _)| name.as_str()).unwrap_or("?");
+					info!("<{}> {}", name, text);
+					let packet = Packet::ChatBroadcast{author: id as u8, text: NetworkText::new(&text), color: CHAT_COLOR};
+					self.broadcast(&packet, None).await;
+				},
+				SystemMessage(target, text, color) => self.send_system_message(target, text, color).await,
+				Shutdown(reason) => {
+					for (_, chan) in self.clients.iter_mut() {
+						let _ = chan.send(ClientAction::Kick(reason.clone())).await;
+					}
+					return Ok(())
+				},
 			}
 		}
 	}
 	
-	pub async fn serve(server: Arc<Mutex<Server>>, world: Arc<Mutex<World>>) -> Result<(), Box<dyn Error>> {
-		let server_lock = server.lock().await;
-		let mut listener = TcpListener::bind(&server_lock.addr).await?;
-		info!("Starting Terraria Server on {}", &server_lock.addr);
+	/// Host world until shutdown resolves, chunks are moved back into the world before returning
+	pub async fn serve(server: Arc<Mutex<Server>>, world: Arc<Mutex<World>>, storage: Arc<dyn WorldStorage>, shutdown: impl Future<Output = ()>) -> Result<(), Box<dyn Error>> {
+		let (addr, max_packet_size, autosave) = { // Lock is released so the server action thread can take it
+			let lock = server.lock().await;
+			(lock.addr.clone(), lock.max_packet_size, Autosave::new(lock.autosave_interval))
+		};
+		let mut listener = TcpListener::bind(&addr).await?;
+		info!("Starting Terraria Server on {}", &addr);
 		
 		// Spawns thread(s) that deal with world management functions
-		let (world_action, world_action_receiver) = mpsc::channel(100);
+		let (mut world_action, world_action_receiver) = mpsc::channel(100);
 		
 		let world_clone = world.clone();
-		tokio::spawn(async move {
+		let world_thread = tokio::spawn(async move {
 			let mut lock = world_clone.lock().await;
-			let result = lock.handle(world_action_receiver).await;
+			let result = lock.handle(world_action_receiver, storage, autosave).await;
 			match result {
 				Err(err) => error!("World Thread Exited with error: {:?}", err),
 				Ok(_) => info!("World thread exited normally"),
@@ -91,10 +187,10 @@ impl Server {
 		});
 		
 		// Action handler that listens on channel (so players can update world)
-		let (server_action, server_receiver) = mpsc::channel(100);
+		let (mut server_action, server_receiver) = mpsc::channel(100);
 		
 		let server_handle = server.clone();
-		tokio::spawn(async move {
+		let server_thread = tokio::spawn(async move {
 			let mut lock = server_handle.lock().await;
 			let result = lock.handle(server_receiver).await;
 			match result {
@@ -103,14 +199,25 @@ impl Server {
 			}
 		});
 		
-		loop {
-			let (socket, _) = listener.accept().await?; // Wait for new connection (or return Err)
+		tokio::pin!(shutdown);
+		let result = loop {
+			let socket = tokio::select! {
+				accepted = listener.accept() => match accepted {
+					Ok((socket, _)) => socket,
+					Err(err) => break Err(err.into()),
+				},
+				_ = &mut shutdown => break Ok(()),
+			};
 			
-			//let mut wld_tx_copy = world_action.clone();
+			let mut wld_tx_copy = world_action.clone();
 			
-			let (mut client, mut action_receiver) = Client::new();
+			let (mut client, mut action_receiver) = Client::new(max_packet_size);
 			
-			//wld_tx_copy.send(WorldAction::SpawnClient(client.action.clone(), None));
+			// World thread sends back the spawn chunk's thread handle
+			if let Err(err) = wld_tx_copy.send(WorldAction::SpawnClient(client.action.clone())).await {
+				error!("Failed to spawn client, world thread has exited: {:?}", err);
+				continue;
+			}
 			
 			let chunk_action = {
 				let recv_action = action_receiver.recv().await;
@@ -134,7 +241,19 @@ impl Server {
 					Ok(_) => info!("Client Disconnected"),
 				}
 			});
+		};
+		
+		info!("Shutting down server");
+		// The world is saved even if the server thread is already gone
+		if server_action.send(ServerAction::Shutdown(NetworkText::new("Server closed"))).await.is_err() {
+			warn!("Server thread exited before shutdown");
+		}
+		if let Err(err) = server_thread.await { error!("Server thread panicked: {:?}", err); }
+		if world_action.send(WorldAction::Shutdown()).await.is_err() {
+			warn!("World thread exited before shutdown");
 		}
-		//Ok(())
+		world_thread.await?;
+		
+		result
 	}
 }
\ No newline at end of file
@@ -12,6 +12,7 @@ use variant_encoding::{VarStringReader, VarIntReader, VarIntWriter};
 
 use arc_swap::ArcSwap;
 use crate::server::player::{self, Player, PlayerError};
+use crate::world::chunk::{TileCoord, TileEdit};
 
 pub mod types;
 use types::*;
@@ -27,11 +28,13 @@ pub enum PacketError {
 	#[error("Packet Not Implemented")]
 	Unimplemented,
 	#[error("Invalid Packet Size: Told: {told}, Found: {found}")]
-	InvalidSize{told: usize, found: usize}
+	InvalidSize{told: usize, found: usize},
+	#[error("Packet Too Large: {size} bytes (max {max})")]
+	TooLarge{size: usize, max: usize},
 }
 
 //File that reads terraria's packets into nice little structures
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Packet {
 	Empty(), // Default value
 	
@@ -47,71 +50,91 @@ pub enum Packet {
 	WorldInfo(ArcSwap<Vec<u8>>), // Information about the world TODO: filter
 	WorldChunk(ArcSwap<Vec<u8>>),
 	Status(i32, NetworkText, u8),
+	TileFrameSection{start_x: i16, start_y: i16, end_x: i16, end_y: i16}, // Tell client to frame tiles in these (200x150) sections
+	StartPlaying, // Client can spawn into world
+	FinishedConnecting, // Sent after client has spawned for the first time
 	
 	// Packets that are received, (possibly modified) and then broadcast to all clients
-	PlayerInfo(String, player::Appearance),
-	PlayerHp{hp: u16, max_hp: u16},
-	PlayerMana{mana: u16, max_mana: u16},
-	PlayerBuff{buffs: [u16; 22]},
-	PlayerInventorySlot{slot_index: u16, amount: u16, item_prefix: u8, net_id: u16},
+	// (Player ID sent by clients is replaced by their server slot when broadcast)
+	PlayerInfo(u8, String, player::Appearance),
+	PlayerHp{id: u8, hp: u16, max_hp: u16},
+	PlayerMana{id: u8, mana: u16, max_mana: u16},
+	PlayerBuff{id: u8, buffs: [u16; 22]},
+	PlayerInventorySlot{id: u8, slot_index: u16, amount: u16, item_prefix: u8, net_id: u16},
+	SpawnPlayer{id: u8, x: i16, y: i16, respawn_timer: i32, context: u8}, // x & y are -1 if spawning at world spawn
+	PlayerActive{id: u8, active: bool}, // Sent to other clients when a player joins or leaves
+	PlayerUpdate{id: u8, movement: player::Movement},
+	TileManipulation{coord: TileCoord, edit: TileEdit},
+	
+	// Text NetModule
+	ChatMessage{command: String, text: String}, // Sent by client (command is e.g. "Say" or "Emote")
+	ChatBroadcast{author: u8, text: NetworkText, color: player::Color}, // Sent by server (author 255 for system messages)
+}
+impl Packet {
+	/// Overwrite the player id of player packets (so clients can't send packets for other players)
+	pub fn set_player_id(&mut self, new_id: u8) {
+		use Packet::*;
+		match self {
+			PlayerInfo(id, ..) => *id = new_id,
+			PlayerHp{id, ..} | PlayerMana{id, ..} | PlayerBuff{id, ..} | PlayerInventorySlot{id, ..}
+			| SpawnPlayer{id, ..} | PlayerActive{id, ..} | PlayerUpdate{id, ..} => *id = new_id,
+			_ => {},
+		}
+	}
 }
 
-#[derive(Default)]
-pub struct PacketCodec;
-impl Decoder for PacketCodec {
-	type Item = Packet;
-	type Error = PacketError;
-	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-		if src.remaining() == 0 { return Ok(None) } // No data to read
-		//println!("Started Reading Packet: Bytes: {:?}, Size: {:?}", src.bytes(), src.remaining());
-		
-		let bytes_left = src.remaining();
-		let size = src.get_u16_le() as usize; //First 2 bytes are size (sometimes?) TODO: figure this out
-		// NetMessage.cs only errors when bytes_left is less than read size
-		if size > bytes_left { return Err(PacketError::InvalidSize{told: size, found: bytes_left}) } // Error: packet size doesn't match what packet says its size should be
-		
-		log::debug!("Read Bytes: {:02x?}", &src.bytes()[0..size-2]);
-		
-		//println!("Bytes Left: {}", src.remaining());
-		let mut reader = src.reader();
-		
-		let msg_type = reader.read_u8()?;
-		//println!("Recevied Packet Type: {}", msg_type);
+/// Id of the Text NetModule (packet 82)
+const TEXT_MODULE_ID: u16 = 1;
+
+/// Largest packet (including its 2 length bytes) the decoder accepts by default, vanilla clients don't send more than a few KB
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 16 * 1024;
 
+pub struct PacketCodec {
+	max_packet_size: usize,
+}
+impl Default for PacketCodec {
+	fn default() -> Self { PacketCodec::new(DEFAULT_MAX_PACKET_SIZE) }
+}
+impl PacketCodec {
+	pub fn new(max_packet_size: usize) -> Self {
+		PacketCodec { max_packet_size }
+	}
+	/// Parse packet body of a given type, returns None if type is unknown
+	fn read_packet(msg_type: u8, reader: &mut impl io::BufRead) -> Result<Option<Packet>, PacketError> {
 		use Packet::*;
 		let packet = match msg_type {
 			1 => ConnectRequest(reader.read_varstring()?),
 			4 => {
-				reader.read_u8()?; // Read Player ID
-				let tuple = player::Player::read_playerinfo(&mut reader)?;
-				PlayerInfo(tuple.0, tuple.1)
+				let id = reader.read_u8()?;
+				let tuple = player::Player::read_playerinfo(reader)?;
+				PlayerInfo(id, tuple.0, tuple.1)
 			}, // Construct player struct
 			68 => PlayerUUID(reader.read_varstring()?),
 			16 => { // Player Health
-				reader.read_u8()?; // Read Player ID
 				PlayerHp {
+					id: reader.read_u8()?,
 					hp: reader.read_u16::<LittleEndian>()?,
 					max_hp: reader.read_u16::<LittleEndian>()?
 				}
 			},
 			42 => { // Player Mana
-				reader.read_u8()?; // Read Player ID
 				PlayerMana {
+					id: reader.read_u8()?,
 					mana: reader.read_u16::<LittleEndian>()?,
 					max_mana: reader.read_u16::<LittleEndian>()?
 				}
 			}
 			50 => { // Player Buffs
-				reader.read_u8()?; // Read Player ID
+				let id = reader.read_u8()?;
 				let mut buffs = [0u16; 22];
 				reader.read_u16_into::<LittleEndian>(&mut buffs)?;
 				PlayerBuff {
-					buffs: buffs
+					id, buffs
 				}
 			}
 			5 => {
-				reader.read_u8()?; // Read Player ID
 				PlayerInventorySlot {
+					id: reader.read_u8()?,
 					slot_index: reader.read_u16::<LittleEndian>()?,
 					amount: reader.read_u16::<LittleEndian>()?,
 					item_prefix: reader.read_u8()?,
@@ -123,15 +146,69 @@ impl Decoder for PacketCodec {
 				reader.read_i32::<LittleEndian>()?,
 				reader.read_i32::<LittleEndian>()?,
 			),
-			_ => Packet::Empty(),
+			13 => PlayerUpdate {
+				id: reader.read_u8()?,
+				movement: player::Movement::read(reader)?,
+			},
+			17 => {
+				let action = reader.read_u8()?;
+				let coord = TileCoord {
+					x: reader.read_i16::<LittleEndian>()? as u16,
+					y: reader.read_i16::<LittleEndian>()? as u16,
+				};
+				let var1 = reader.read_i16::<LittleEndian>()?;
+				let var2 = reader.read_u8()?;
+				TileManipulation{coord, edit: TileEdit::from_raw(action, var1, var2)}
+			},
+			82 => match reader.read_u16::<LittleEndian>()? { // NetModules
+				TEXT_MODULE_ID => ChatMessage {
+					command: reader.read_varstring()?,
+					text: reader.read_varstring()?,
+				},
+				_ => return Ok(None),
+			},
+			12 => {
+				SpawnPlayer {
+					id: reader.read_u8()?,
+					x: reader.read_i16::<LittleEndian>()?,
+					y: reader.read_i16::<LittleEndian>()?,
+					respawn_timer: reader.read_i32::<LittleEndian>()?,
+					context: reader.read_u8()?,
+				}
+			}
+			_ => return Ok(None),
 		};
-		//println!("Finished Reading Packet: Bytes: {:?}, Size: {:?}", src.bytes(), src.remaining());
-		//println!("Finished Reading Packet: Bytes: {:?}, Size: {:?}", src.bytes(), src.remaining());
-		
-		if std::mem::discriminant(&packet) == std::mem::discriminant(&Packet::Empty()) { return Err(PacketError::UnknownType(msg_type)) }
 		Ok(Some(packet))
 	}
 }
+impl Decoder for PacketCodec {
+	type Item = Packet;
+	type Error = PacketError;
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		loop {
+			if src.len() < 2 { return Ok(None) } // Wait for length prefix
+			
+			let size = u16::from_le_bytes([src[0], src[1]]) as usize; // Size includes the 2 length bytes
+			if size < 3 { return Err(PacketError::InvalidSize{told: size, found: src.len()}) } // Too small to have a packet type
+			if size > self.max_packet_size { return Err(PacketError::TooLarge{size, max: self.max_packet_size}) }
+			if src.len() < size { // Wait for rest of packet to arrive
+				src.reserve(size - src.len());
+				return Ok(None)
+			}
+			
+			let mut frame = src.split_to(size);
+			frame.advance(2);
+			log::debug!("Read Bytes: {:02x?}", &frame[..]);
+			
+			let msg_type = frame.get_u8();
+			let mut reader = frame.reader();
+			match PacketCodec::read_packet(msg_type, &mut reader)? {
+				Some(packet) => return Ok(Some(packet)),
+				None => log::trace!("Skipping Unknown Packet Type: {}", msg_type), // Already skipped by length, try next frame
+			}
+		}
+	}
+}
 impl Encoder<&Packet> for PacketCodec {
 	type Error = PacketError;
 	fn encode(&mut self, item: &Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
@@ -146,7 +223,8 @@ impl Encoder<&Packet> for PacketCodec {
 			},
 			Disconnect(localized_text) => {
 				localized_text.write(&mut writer)?;
-				dst.put_u16_le(writer.len() as u16 + 2);
+				dst.put_u16_le(writer.len() as u16 + 3);
+				dst.put_u8(2); // Packet ID
 			}
 			WorldInfo(info) => { // Receives locked reader (managed by calling function)
 				let arc = info.load();
@@ -157,13 +235,112 @@ impl Encoder<&Packet> for PacketCodec {
 				//println!("Data Length: {}", writer.len());
 				//println!("Data Hex: {:02X?}", &writer[..]);
 			}
+			WorldChunk(section) => { // Compressed section data (cached by chunk thread)
+				let arc = section.load();
+				if arc.len() + 3 > u16::MAX as usize { return Err(PacketError::TooLarge{size: arc.len() + 3, max: u16::MAX as usize}) }
+				writer.write_u16::<LittleEndian>(arc.len() as u16 + 3)?;
+				writer.write_u8(10)?; // Packet ID
+				use std::io::Write;
+				writer.write_all(&arc[..])?;
+			}
 			Status(max, localized_text, flags) => {
 				writer.write_i32::<LittleEndian>(*max)?;
 				localized_text.write(&mut writer)?;
 				writer.write_u8(*flags)?;
 				
+				dst.put_u16_le(writer.len() as u16 + 3);
+				dst.put_u8(9); // Packet ID
+			}
+			TileFrameSection{start_x, start_y, end_x, end_y} => {
+				writer.write_u16::<LittleEndian>(11)?;
+				writer.write_u8(11)?; // Packet ID
+				writer.write_i16::<LittleEndian>(*start_x)?;
+				writer.write_i16::<LittleEndian>(*start_y)?;
+				writer.write_i16::<LittleEndian>(*end_x)?;
+				writer.write_i16::<LittleEndian>(*end_y)?;
+			}
+			StartPlaying => {
+				writer.write_u16::<LittleEndian>(3)?;
+				writer.write_u8(49)?; // Packet ID
+			}
+			PlayerUpdate{id, movement} => {
+				writer.write_u8(13)?; // Packet ID
+				writer.write_u8(*id)?;
+				movement.write(&mut writer)?;
+				dst.put_u16_le(writer.len() as u16 + 2);
+			}
+			TileManipulation{coord, edit} => {
+				let (action, var1, var2) = edit.to_raw();
+				writer.write_u16::<LittleEndian>(11)?;
+				writer.write_u8(17)?; // Packet ID
+				writer.write_u8(action)?;
+				writer.write_i16::<LittleEndian>(coord.x as i16)?;
+				writer.write_i16::<LittleEndian>(coord.y as i16)?;
+				writer.write_i16::<LittleEndian>(var1)?;
+				writer.write_u8(var2)?;
+			}
+			PlayerInfo(id, name, appearance) => {
+				writer.write_u8(4)?; // Packet ID
+				writer.write_u8(*id)?;
+				player::Player::write_playerinfo(&mut writer, name, appearance)?;
+				dst.put_u16_le(writer.len() as u16 + 2);
+			}
+			PlayerInventorySlot{id, slot_index, amount, item_prefix, net_id} => {
+				writer.write_u16::<LittleEndian>(11)?;
+				writer.write_u8(5)?; // Packet ID
+				writer.write_u8(*id)?;
+				writer.write_u16::<LittleEndian>(*slot_index)?;
+				writer.write_u16::<LittleEndian>(*amount)?;
+				writer.write_u8(*item_prefix)?;
+				writer.write_u16::<LittleEndian>(*net_id)?;
+			}
+			SpawnPlayer{id, x, y, respawn_timer, context} => {
+				writer.write_u8(12)?; // Packet ID
+				writer.write_u8(*id)?;
+				writer.write_i16::<LittleEndian>(*x)?;
+				writer.write_i16::<LittleEndian>(*y)?;
+				writer.write_i32::<LittleEndian>(*respawn_timer)?;
+				writer.write_u8(*context)?;
 				dst.put_u16_le(writer.len() as u16 + 2);
 			}
+			PlayerActive{id, active} => {
+				writer.write_u16::<LittleEndian>(5)?;
+				writer.write_u8(14)?; // Packet ID
+				writer.write_u8(*id)?;
+				writer.write_u8(*active as u8)?;
+			}
+			PlayerHp{id, hp, max_hp} => {
+				writer.write_u16::<LittleEndian>(8)?;
+				writer.write_u8(16)?; // Packet ID
+				writer.write_u8(*id)?;
+				writer.write_u16::<LittleEndian>(*hp)?;
+				writer.write_u16::<LittleEndian>(*max_hp)?;
+			}
+			PlayerMana{id, mana, max_mana} => {
+				writer.write_u16::<LittleEndian>(8)?;
+				writer.write_u8(42)?; // Packet ID
+				writer.write_u8(*id)?;
+				writer.write_u16::<LittleEndian>(*mana)?;
+				writer.write_u16::<LittleEndian>(*max_mana)?;
+			}
+			PlayerBuff{id, buffs} => {
+				writer.write_u16::<LittleEndian>(4 + 2 * buffs.len() as u16)?;
+				writer.write_u8(50)?; // Packet ID
+				writer.write_u8(*id)?;
+				for buff in buffs.iter() { writer.write_u16::<LittleEndian>(*buff)?; }
+			}
+			ChatBroadcast{author, text, color} => {
+				writer.write_u8(82)?; // Packet ID
+				writer.write_u16::<LittleEndian>(TEXT_MODULE_ID)?;
+				writer.write_u8(*author)?;
+				text.write(&mut writer)?;
+				color.write(&mut writer)?;
+				dst.put_u16_le(writer.len() as u16 + 2);
+			}
+			FinishedConnecting => {
+				writer.write_u16::<LittleEndian>(3)?;
+				writer.write_u8(129)?; // Packet ID
+			}
 			_ => return Err(PacketError::Unimplemented),
 		};
 		
@@ -171,4 +348,121 @@ impl Encoder<&Packet> for PacketCodec {
 		log::debug!("Write Bytes: {:02x?}", dst.bytes());
 		Ok(())
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	/// ConnectRequest("Terraria230") frame
+	fn connect_request() -> Vec<u8> {
+		let mut frame = vec![15, 0, 1, 11];
+		frame.extend_from_slice(b"Terraria230");
+		frame
+	}
+	
+	#[test]
+	fn decode_byte_by_byte() {
+		let mut codec = PacketCodec::default();
+		let mut src = BytesMut::new();
+		let frame = connect_request();
+		for (i, byte) in frame.iter().enumerate() {
+			src.put_u8(*byte);
+			let result = codec.decode(&mut src).unwrap();
+			if i < frame.len() - 1 {
+				assert!(result.is_none(), "decoded packet before frame was complete");
+			} else {
+				match result { Some(Packet::ConnectRequest(s)) => assert_eq!(s, "Terraria230"), other => panic!("Wrong packet: {:?}", other) }
+			}
+		}
+		assert!(src.is_empty());
+	}
+	
+	#[test]
+	fn skip_unknown_packets() {
+		let mut codec = PacketCodec::default();
+		let mut src = BytesMut::new();
+		src.put_slice(&[7, 0, 200, 1, 2, 3, 4]); // Unknown packet type 200 with 4 byte body
+		src.put_slice(&[3, 0, 6]); // WorldDataRequest
+		assert!(matches!(codec.decode(&mut src).unwrap(), Some(Packet::WorldDataRequest)));
+		assert!(src.is_empty());
+	}
+	
+	#[test]
+	fn unknown_packet_split_across_reads() {
+		let mut codec = PacketCodec::default();
+		let mut src = BytesMut::new();
+		let mut input = vec![7, 0, 200, 1, 2, 3, 4];
+		input.extend(connect_request());
+		
+		let mut decoded = vec![];
+		for byte in input {
+			src.put_u8(byte);
+			if let Some(packet) = codec.decode(&mut src).unwrap() { decoded.push(packet); }
+		}
+		assert_eq!(decoded.len(), 1);
+		assert!(matches!(decoded[0], Packet::ConnectRequest(_)));
+	}
+	
+	#[test]
+	fn multiple_packets_in_buffer() {
+		let mut codec = PacketCodec::default();
+		let mut src = BytesMut::new();
+		src.put_slice(&connect_request());
+		src.put_slice(&[11, 0, 8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]); // EssentialTilesRequest(-1, -1)
+		
+		assert!(matches!(codec.decode(&mut src).unwrap(), Some(Packet::ConnectRequest(_))));
+		assert!(matches!(codec.decode(&mut src).unwrap(), Some(Packet::EssentialTilesRequest(-1, -1))));
+		assert!(codec.decode(&mut src).unwrap().is_none());
+	}
+	
+	#[test]
+	fn reject_oversized_packets() {
+		let mut codec = PacketCodec::new(64);
+		let mut src = BytesMut::new();
+		src.put_slice(&[65, 0]); // Only the length has arrived
+		assert!(matches!(codec.decode(&mut src), Err(PacketError::TooLarge{size: 65, max: 64})));
+	}
+	
+	#[test]
+	fn spawn_player_round_trip() {
+		let mut codec = PacketCodec::default();
+		let mut buffer = BytesMut::new();
+		codec.encode(&Packet::SpawnPlayer{id: 3, x: -1, y: 250, respawn_timer: 600, context: 1}, &mut buffer).unwrap();
+		codec.encode(&Packet::StartPlaying, &mut buffer).unwrap(); // Next packet has to be framed right too
+		assert_eq!(&buffer[..2], &[13, 0]);
+		
+		match codec.decode(&mut buffer).unwrap() {
+			Some(Packet::SpawnPlayer{id, x, y, respawn_timer, context}) => assert_eq!((id, x, y, respawn_timer, context), (3, -1, 250, 600, 1)),
+			other => panic!("Wrong packet: {:?}", other),
+		}
+		assert_eq!(&buffer[..], &[3, 0, 49]);
+	}
+	
+	#[test]
+	fn default_size_limit() {
+		let mut codec = PacketCodec::default();
+		let mut src = BytesMut::new();
+		src.put_slice(&(DEFAULT_MAX_PACKET_SIZE as u16 + 1).to_le_bytes());
+		assert!(matches!(codec.decode(&mut src), Err(PacketError::TooLarge{..})));
+	}
+	
+	#[test]
+	fn reject_oversized_section() {
+		let mut codec = PacketCodec::default();
+		let mut dst = BytesMut::new();
+		let section = Packet::WorldChunk(ArcSwap::new(Arc::new(vec![0; u16::MAX as usize - 2])));
+		assert!(matches!(codec.encode(&section, &mut dst), Err(PacketError::TooLarge{size: 65536, ..})));
+		assert!(dst.is_empty());
+		let section = Packet::WorldChunk(ArcSwap::new(Arc::new(vec![0; u16::MAX as usize - 3])));
+		codec.encode(&section, &mut dst).unwrap();
+		assert_eq!(&dst[..3], &[0xff, 0xff, 10]);
+	}
+	
+	#[test]
+	fn reject_truncated_body() {
+		let mut codec = PacketCodec::default();
+		let mut src = BytesMut::new();
+		src.put_slice(&[5, 0, 8, 0, 0]); // EssentialTilesRequest with only 2 of its 8 bytes
+		assert!(matches!(codec.decode(&mut src), Err(PacketError::CodecError(_))));
+	}
+}
@@ -9,7 +9,7 @@ use variant_encoding::{VarStringReader, VarStringWriter};
 enum NetworkTextMode {
 	Literal = 0u8, Formattable = 1, LocalizationKey = 2,
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct NetworkText {
 	mode: NetworkTextMode,
 	text: String,
@@ -23,6 +23,14 @@ impl NetworkText {
 			substitution: vec![],
 		}
 	}
+	/// Text that is looked up in the client's language files (e.g. "LegacyInterface.44")
+	pub fn localized(key: &str) -> Self {
+		NetworkText {
+			mode: NetworkTextMode::LocalizationKey,
+			text: key.to_owned(),
+			substitution: vec![],
+		}
+	}
 	pub fn write(&self, writer: &mut impl io::Write) -> Result<(), io::Error> {
 		writer.write_u8(self.mode as u8)?;
 		writer.write_varstring(&self.text)?;
@@ -27,6 +27,42 @@ bitflags! {
 		const Creative 		= 0b00001000; // 8
 	}
 }
+bitflags! {
+	#[derive(Default)]
+	pub struct Control: u8 {
+		const Up 		= 0b00000001;
+		const Down 		= 0b00000010;
+		const Left 		= 0b00000100;
+		const Right 	= 0b00001000;
+		const Jump 		= 0b00010000;
+		const UseItem 	= 0b00100000;
+		const Direction = 0b01000000; // Set if facing right
+	}
+}
+bitflags! {
+	#[derive(Default)]
+	pub struct Pulley: u8 {
+		const Enabled 			= 0b00000001;
+		const Direction 		= 0b00000010;
+		const HasVelocity 		= 0b00000100; // Velocity is sent with packet
+		const VortexStealth 	= 0b00001000;
+		const GravityDirection 	= 0b00010000; // Set if gravity is normal (not flipped)
+		const ShieldRaised 		= 0b00100000;
+	}
+}
+bitflags! {
+	#[derive(Default)]
+	pub struct MiscMovement: u8 {
+		const HoveringUp 		= 0b00000001;
+		const VoidVault 		= 0b00000010;
+		const Sitting 			= 0b00000100;
+		const DownedDD2Event 	= 0b00001000;
+		const PettingAnimal 	= 0b00010000;
+		const SmallAnimal 		= 0b00100000;
+		const UsedReturnPotion 	= 0b01000000; // Return potion positions are sent with packet
+		const HoveringDown 		= 0b10000000;
+	}
+}
 bitflags! {
 	#[derive(Default)]
 	pub struct TorchState: u8 {
@@ -34,11 +70,14 @@ bitflags! {
 		const HappyFunTorchTime = 0b00000010; // 2
 	}
 }
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
 pub struct Color {
-	r: u8, g: u8, b: u8,
+	pub r: u8, pub g: u8, pub b: u8,
 }
 impl Color {
+	pub const fn new(r: u8, g: u8, b: u8) -> Color {
+		Color { r, g, b }
+	}
 	pub fn read(reader: &mut impl io::BufRead) -> Result<Color, io::Error> {
 		Ok(Color {
 			r: reader.read_u8()?,
@@ -46,6 +85,12 @@ impl Color {
 			b: reader.read_u8()?,
 		})
 	}
+	pub fn write(&self, writer: &mut impl io::Write) -> Result<(), io::Error> {
+		writer.write_u8(self.r)?;
+		writer.write_u8(self.g)?;
+		writer.write_u8(self.b)?;
+		Ok(())
+	}
 }
 #[derive(Default, Debug, PartialEq, Clone, Copy)]
 pub struct Item {
@@ -105,7 +150,7 @@ impl Default for Inventory {
 }
 impl Inventory {
 	pub fn update_slot(&mut self, packet: Packet) -> Result<(), PlayerError> {
-		if let Packet::PlayerInventorySlot{slot_index, amount, item_prefix, net_id} = packet {
+		if let Packet::PlayerInventorySlot{slot_index, amount, item_prefix, net_id, ..} = packet {
 			// TODO: Make sure items sent can actually be stored in character slots
 			let index = slot_index as usize;
 			let item = Item{id: net_id, amount: amount, prefix: item_prefix};
@@ -126,9 +171,25 @@ impl Inventory {
 		}
 		Ok(())
 	}
+	pub fn get_slot(&self, index: usize) -> Option<Item> {
+		match index {
+			0..=58 => self.inventory.get(index).copied(),
+			179 => Some(self.trash),
+			59..=78 => self.armor.get(index - 59).copied(),
+			79..=88 => self.dye.get(index - 79).copied(),
+			89..=93 => self.misc_equips.get(index - 89).copied(),
+			94..=98 => self.misc_dyes.get(index - 94).copied(),
+			
+			99..=138 => self.piggy_bank.get(index - 99).copied(),
+			139..=178 => self.safe.get(index - 139).copied(),
+			180..=219 => self.defenders_forge.get(index - 180).copied(),
+			220..=259 => self.void_vault.get(index - 220).copied(),
+			_ => None,
+		}
+	}
 }
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone)]
 pub struct Appearance {
 	pub skin: u8,
 	pub hair: u8,
@@ -162,21 +223,21 @@ use crate::packet::Packet;
 impl Status {
 	pub fn init(&mut self, packet: Packet) -> Result<(), PlayerError> {
 		match packet {
-			Packet::PlayerHp{hp: _, max_hp} => {
+			Packet::PlayerHp{hp: _, max_hp, ..} => {
 				if self.max_hp == 0 {
 					self.max_hp = max_hp;
 					self.hp = self.max_hp;
 					Ok(())
 				} else { Err(PlayerError::WrongField("Can't Modify Hp")) }
 			},
-			Packet::PlayerMana{mana: _, max_mana} => {
+			Packet::PlayerMana{mana: _, max_mana, ..} => {
 				if self.max_mana == 0 {
 					self.max_mana = max_mana;
 					self.mana = self.max_mana;
 					Ok(())
 				} else { Err(PlayerError::WrongField("Can't Modify Mana")) }
 			},
-			Packet::PlayerBuff{buffs} => {
+			Packet::PlayerBuff{buffs, ..} => {
 				if self.buffs == [0u16; 22] {
 					self.buffs = buffs;
 					Ok(())
@@ -185,6 +246,92 @@ impl Status {
 			_ => Err(PlayerError::WrongField("Unknown Status Packet")),
 		}
 	}
+	/// Update status with values sent by client
+	pub fn update(&mut self, packet: Packet) -> Result<(), PlayerError> {
+		match packet {
+			Packet::PlayerHp{hp, max_hp, ..} => { self.hp = hp; self.max_hp = max_hp; },
+			Packet::PlayerMana{mana, max_mana, ..} => { self.mana = mana; self.max_mana = max_mana; },
+			Packet::PlayerBuff{buffs, ..} => self.buffs = buffs,
+			_ => return Err(PlayerError::WrongField("Unknown Status Packet")),
+		}
+		Ok(())
+	}
+}
+
+/// Position in pixels (16 per tile)
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
+pub struct Vector2 {
+	pub x: f32,
+	pub y: f32,
+}
+impl Vector2 {
+	pub fn read(reader: &mut impl io::Read) -> Result<Vector2, io::Error> {
+		Ok(Vector2 {
+			x: reader.read_f32::<LittleEndian>()?,
+			y: reader.read_f32::<LittleEndian>()?,
+		})
+	}
+	pub fn write(&self, writer: &mut impl io::Write) -> Result<(), io::Error> {
+		writer.write_f32::<LittleEndian>(self.x)?;
+		writer.write_f32::<LittleEndian>(self.y)?;
+		Ok(())
+	}
+	pub fn to_tilecoord(self) -> TileCoord {
+		TileCoord { x: (self.x.max(0.0) / 16.0) as u16, y: (self.y.max(0.0) / 16.0) as u16 }
+	}
+}
+
+/// Movement state sent by clients in PlayerUpdate packets
+#[derive(Default, Debug, PartialEq, Clone)]
+pub struct Movement {
+	pub control: Control,
+	pub pulley: Pulley,
+	pub misc: MiscMovement,
+	pub is_sleeping: bool,
+	pub selected_item: u8,
+	pub position: Vector2,
+	pub velocity: Option<Vector2>,
+	pub return_potion: Option<(Vector2, Vector2)>, // Original use position & home position
+}
+impl Movement {
+	pub fn read(reader: &mut impl io::Read) -> Result<Movement, io::Error> {
+		let mut movement = Movement {
+			control: Control::from_bits_truncate(reader.read_u8()?),
+			pulley: Pulley::from_bits_truncate(reader.read_u8()?),
+			misc: MiscMovement::from_bits_truncate(reader.read_u8()?),
+			is_sleeping: reader.read_u8()? & 1 != 0,
+			selected_item: reader.read_u8()?,
+			position: Vector2::read(reader)?,
+			..Movement::default()
+		};
+		if movement.pulley.contains(Pulley::HasVelocity) {
+			movement.velocity = Some(Vector2::read(reader)?);
+		}
+		if movement.misc.contains(MiscMovement::UsedReturnPotion) {
+			movement.return_potion = Some((Vector2::read(reader)?, Vector2::read(reader)?));
+		}
+		Ok(movement)
+	}
+	pub fn write(&self, writer: &mut impl io::Write) -> Result<(), io::Error> {
+		// Optional fields decide their own flags
+		let mut pulley = self.pulley;
+		pulley.set(Pulley::HasVelocity, self.velocity.is_some());
+		let mut misc = self.misc;
+		misc.set(MiscMovement::UsedReturnPotion, self.return_potion.is_some());
+		
+		writer.write_u8(self.control.bits())?;
+		writer.write_u8(pulley.bits())?;
+		writer.write_u8(misc.bits())?;
+		writer.write_u8(self.is_sleeping as u8)?;
+		writer.write_u8(self.selected_item)?;
+		self.position.write(writer)?;
+		if let Some(velocity) = &self.velocity { velocity.write(writer)?; }
+		if let Some((original, home)) = &self.return_potion {
+			original.write(writer)?;
+			home.write(writer)?;
+		}
+		Ok(())
+	}
 }
 
 #[derive(Default, Debug, PartialEq)]
@@ -193,6 +340,7 @@ pub struct Player {
 	pub uuid: String, // uuid of the player TODO: what is this used for?
 	pub name: String,
 	pub position: TileCoord,
+	pub movement: Movement, // Last movement state sent by client
 	
 	pub status: Status, // Holds hp, mana, buffs etc.
 	pub inventory: Inventory, // Whats in your inventory?
@@ -202,6 +350,48 @@ pub struct Player {
 	pub torch_state: TorchState,
 }
 impl Player {
+	pub fn update_movement(&mut self, movement: Movement) {
+		self.position = movement.position.to_tilecoord();
+		self.movement = movement;
+	}
+	/// Packets that show this player (appearance, stats, position and visible equipment) to other clients
+	pub fn state_packets(&self) -> Vec<Packet> {
+		let id = self.id;
+		let mut packets = vec![
+			Packet::PlayerActive{id, active: true},
+			Packet::PlayerInfo(id, self.name.clone(), self.appearance.clone()),
+			Packet::PlayerUpdate{id, movement: self.movement.clone()},
+			Packet::PlayerHp{id, hp: self.status.hp, max_hp: self.status.max_hp},
+			Packet::PlayerMana{id, mana: self.status.mana, max_mana: self.status.max_mana},
+			Packet::PlayerBuff{id, buffs: self.status.buffs},
+		];
+		// Inventory (for held item), armor, accessories, dyes and misc equips
+		for index in 0..=98 {
+			if let Some(item) = self.inventory.get_slot(index) {
+				packets.push(Packet::PlayerInventorySlot{id, slot_index: index as u16, amount: item.amount, item_prefix: item.prefix, net_id: item.id});
+			}
+		}
+		packets
+	}
+	pub fn write_playerinfo(writer: &mut impl io::Write, name: &str, appearance: &Appearance) -> Result<(), io::Error> {
+		writer.write_u8(appearance.skin)?;
+		writer.write_u8(appearance.hair)?;
+		writer.write_varstring(name)?;
+		writer.write_u8(appearance.hair_dye)?;
+		writer.write_u8(appearance.hide_visuals_1)?;
+		writer.write_u8(appearance.hide_visuals_2)?;
+		writer.write_u8(appearance.hide_misc)?;
+		appearance.hair_color.write(writer)?;
+		appearance.skin_color.write(writer)?;
+		appearance.eye_color.write(writer)?;
+		appearance.shirt_color.write(writer)?;
+		appearance.under_shift_color.write(writer)?;
+		appearance.pants_color.write(writer)?;
+		appearance.shoe_color.write(writer)?;
+		writer.write_u8(appearance.unknown_trait)?;
+		writer.write_u8(appearance.unknown_trait2)?;
+		Ok(())
+	}
 	pub fn read_playerinfo(reader: &mut impl io::BufRead) -> Result<(String, Appearance), crate::packet::PacketError> {
 		let mut appearance = Appearance::default();
 		appearance.skin = reader.read_u8()?;
@@ -0,0 +1,23 @@
+use log::warn;
+use std::time::Duration;
+
+use super::{ChunkCoord, storage::{WorldStorage, StorageError}};
+
+/// Autosaves write the world metadata and the chunks modified since the last save to storage
+#[derive(Debug, Clone)]
+pub struct Autosave {
+	pub interval: Duration,
+}
+impl Autosave {
+	/// None if autosaving is disabled (interval of 0)
+	pub fn new(interval_secs: u64) -> Option<Autosave> {
+		if interval_secs == 0 { return None }
+		Some(Autosave { interval: Duration::from_secs(interval_secs) })
+	}
+	/// Back up the last save, then write world metadata & compressed chunks (from `Chunk::write`)
+	pub fn write(&self, metadata: &[u8], chunks: &[(ChunkCoord, Vec<u8>)], storage: &dyn WorldStorage) -> Result<(), StorageError> {
+		if let Err(err) = storage.backup() { warn!("Failed to back up world, saving anyway: {:?}", err); }
+		storage.save_chunks_data(chunks)?;
+		storage.save_metadata(metadata)
+	}
+}
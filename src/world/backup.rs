@@ -0,0 +1,250 @@
+use log::{info, warn};
+use std::{
+	io,
+	cmp::Reverse,
+	error::Error,
+	fs::{self, File},
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{World, storage::RegionStorage};
+
+/// Backups of a world file are kept in a directory next to it (e.g. world.twld.backups/world.twld.<unix time>),
+/// with a copy of its region files (world.twld.backups/world.twld.<unix time>.regions/) if it has any
+fn backup_dir(world_path: &Path) -> PathBuf {
+	let mut dir = world_path.as_os_str().to_owned();
+	dir.push(".backups");
+	PathBuf::from(dir)
+}
+
+/// Write data to path through a temporary file, so path always holds either the old or the new data
+pub fn write_atomic<E: From<io::Error>>(path: &Path, write: impl FnOnce(&mut File) -> Result<(), E>) -> Result<(), E> {
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+
+	let mut file = File::create(&tmp_path)?;
+	write(&mut file)?;
+	file.sync_all()?;
+	fs::rename(&tmp_path, path)?;
+	Ok(sync_parent(path)?)
+}
+/// Make sure the rename made it to disk
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<(), io::Error> {
+	match path.parent() {
+		Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+		_ => File::open(".")?.sync_all(),
+	}
+}
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<(), io::Error> { Ok(()) }
+
+/// Backups of world file, newest first (unix time of backup, path)
+pub fn list(world_path: impl AsRef<Path>) -> Result<Vec<(u64, PathBuf)>, io::Error> {
+	let world_path = world_path.as_ref();
+	let dir = backup_dir(world_path);
+	if !dir.exists() { return Ok(vec![]) }
+	let prefix = format!("{}.", world_path.file_name().and_then(|name| name.to_str()).unwrap_or_default());
+
+	let mut backups = vec![];
+	for entry in fs::read_dir(&dir)? {
+		let path = entry?.path();
+		let time = path.file_name().and_then(|name| name.to_str())
+			.and_then(|name| name.strip_prefix(&prefix))
+			.and_then(|time| time.parse::<u64>().ok());
+		if let Some(time) = time { backups.push((time, path)); }
+	}
+	backups.sort_by_key(|(time, _)| Reverse(*time));
+	Ok(backups)
+}
+
+/// Keep a copy of the current world file & its region files (if there is one) and remove all but the newest `keep` backups
+pub fn backup(world_path: impl AsRef<Path>, keep: usize) -> Result<(), io::Error> {
+	let world_path = world_path.as_ref();
+	if keep == 0 { return Ok(()) }
+	copy_current(world_path)?;
+	prune(world_path, keep)
+}
+/// Keep a copy of the file at path (if there is one) made by `copy`, which is given the path of the backup.
+/// Old backups are left for `prune`.
+pub fn backup_with<E: From<io::Error>>(path: impl AsRef<Path>, copy: impl FnOnce(&Path) -> Result<(), E>) -> Result<(), E> {
+	let path = path.as_ref();
+	if !path.exists() { return Ok(()) }
+	copy(&new_backup_path(path)?)
+}
+/// Path for a backup made now (a second later if a backup was already made this second, so it isn't overwritten)
+fn new_backup_path(path: &Path) -> Result<PathBuf, io::Error> {
+	let dir = backup_dir(path);
+	fs::create_dir_all(&dir)?;
+
+	let mut time = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+	loop {
+		let mut name = path.file_name().unwrap_or_default().to_owned();
+		name.push(format!(".{}", time));
+		let backup_path = dir.join(name);
+		if !backup_path.exists() && !RegionStorage::dir_for_world(&backup_path).exists() { return Ok(backup_path) }
+		time += 1;
+	}
+}
+fn copy_current(world_path: &Path) -> Result<(), io::Error> {
+	if !world_path.exists() { return Ok(()) }
+	let backup_path = new_backup_path(world_path)?;
+	// Hard link is free, the world file is replaced by rename so the backup keeps the old contents
+	if fs::hard_link(world_path, &backup_path).is_err() {
+		fs::copy(world_path, &backup_path)?;
+	}
+	// Region files are written in place, so they have to be copied
+	let regions = RegionStorage::dir_for_world(world_path);
+	if regions.is_dir() { copy_dir(&regions, &RegionStorage::dir_for_world(&backup_path))?; }
+	Ok(())
+}
+fn copy_dir(from: &Path, to: &Path) -> Result<(), io::Error> {
+	fs::create_dir_all(to)?;
+	for entry in fs::read_dir(from)? {
+		let entry = entry?;
+		if entry.file_type()?.is_file() { fs::copy(entry.path(), to.join(entry.file_name()))?; }
+	}
+	Ok(())
+}
+fn remove_backup(backup_path: &Path) -> Result<(), io::Error> {
+	if backup_path.exists() { fs::remove_file(backup_path)?; }
+	let regions = RegionStorage::dir_for_world(backup_path);
+	if regions.exists() { fs::remove_dir_all(regions)?; }
+	Ok(())
+}
+/// Remove all but the newest `keep` backups of path
+pub fn prune(path: impl AsRef<Path>, keep: usize) -> Result<(), io::Error> {
+	let path = path.as_ref();
+	for (_, old) in list(path)?.into_iter().skip(keep) {
+		if let Err(err) = remove_backup(&old) {
+			warn!("Failed to remove old backup {:?}: {:?}", old, err);
+		}
+	}
+	Ok(())
+}
+
+/// Replace world file & its region files with a backup (the current world is backed up first).
+/// Must not be called while the world is hosted.
+pub fn restore(world_path: impl AsRef<Path>, backup_path: &Path, keep: usize) -> Result<(), Box<dyn Error>> {
+	let world_path = world_path.as_ref();
+	copy_current(world_path)?;
+	
+	let (regions, backup_regions) = (RegionStorage::dir_for_world(world_path), RegionStorage::dir_for_world(backup_path));
+	if backup_regions.is_dir() {
+		// Copied next to the current regions first, so a failed copy leaves them alone
+		let mut tmp = regions.as_os_str().to_owned();
+		tmp.push(".tmp");
+		let tmp = PathBuf::from(tmp);
+		if tmp.exists() { fs::remove_dir_all(&tmp)?; }
+		copy_dir(&backup_regions, &tmp)?;
+		if regions.exists() { fs::remove_dir_all(&regions)?; }
+		fs::rename(&tmp, &regions)?;
+	} else if !World::read(&mut io::BufReader::new(File::open(backup_path)?))?.chunks.is_empty() {
+		// Chunks are in the backed up world file, they're moved to new region files when it's loaded
+		if regions.exists() { fs::remove_dir_all(&regions)?; }
+	} else {
+		warn!("Backup {:?} has no chunks, keeping the current ones", backup_path);
+	}
+	write_atomic(world_path, |file| -> Result<(), io::Error> {
+		io::copy(&mut File::open(backup_path)?, file)?;
+		Ok(())
+	})?;
+	prune(world_path, keep.max(1))?;
+	info!("Restored {:?} from {:?}", world_path, backup_path);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Write;
+	use crate::world::ChunkCoord;
+
+	fn temp_world_path(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("terrarium-backup-test-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir.join("world.twld")
+	}
+	fn fake_backup(world_path: &Path, time: u64, contents: &str) -> PathBuf {
+		let dir = backup_dir(world_path);
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join(format!("world.twld.{}", time));
+		fs::write(&path, contents).unwrap();
+		path
+	}
+
+	#[test]
+	fn backup_keeps_old_contents() {
+		let path = temp_world_path("keep");
+		backup(&path, 3).unwrap(); // Nothing to back up yet
+		assert!(list(&path).unwrap().is_empty());
+
+		fs::write(&path, "old").unwrap();
+		backup(&path, 3).unwrap();
+		write_atomic(&path, |file| -> Result<(), io::Error> { file.write_all(b"new") }).unwrap();
+		let backups = list(&path).unwrap();
+		assert_eq!(backups.len(), 1);
+		assert_eq!(fs::read_to_string(&backups[0].1).unwrap(), "old");
+		assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+		let _ = fs::remove_dir_all(path.parent().unwrap());
+	}
+	#[test]
+	fn prune_keeps_newest() {
+		let path = temp_world_path("prune");
+		fs::write(&path, "current").unwrap();
+		for time in [10, 30, 20, 40].iter() { fake_backup(&path, *time, "old"); }
+		fs::write(backup_dir(&path).join("notes.txt"), "not a backup").unwrap();
+
+		prune(&path, 2).unwrap();
+		let times: Vec<u64> = list(&path).unwrap().into_iter().map(|(time, _)| time).collect();
+		assert_eq!(times, vec![40, 30]);
+		assert!(backup_dir(&path).join("notes.txt").exists());
+		let _ = fs::remove_dir_all(path.parent().unwrap());
+	}
+	#[test]
+	fn restore_replaces_metadata_and_chunks() {
+		let path = temp_world_path("restore");
+		let write_world = |name: &str| {
+			let world = World { name: name.into(), ..World::default() };
+			write_atomic(&path, |file| world.write(file)).unwrap();
+		};
+		let read_name = |path: &Path| World::read(&mut File::open(path).unwrap()).unwrap().name;
+		let regions = RegionStorage::for_world(&path);
+		let (a, b) = (ChunkCoord { x: 1, y: 2 }, ChunkCoord { x: 40, y: 2 });
+		write_world("before");
+		regions.save_chunk(&a, b"before").unwrap();
+		backup(&path, 5).unwrap();
+
+		write_world("after");
+		regions.save_chunk(&a, b"after").unwrap();
+		regions.save_chunk(&b, b"new").unwrap();
+		let before = list(&path).unwrap().remove(0).1;
+		restore(&path, &before, 5).unwrap();
+		assert_eq!(read_name(&path), "before");
+		assert_eq!(regions.load_chunk_data(&a).unwrap().unwrap(), b"before");
+		assert!(regions.load_chunk_data(&b).unwrap().is_none());
+
+		// The replaced world was backed up with its chunks
+		let backups = list(&path).unwrap();
+		assert_eq!(backups.len(), 2);
+		assert_eq!(read_name(&backups[0].1), "after");
+		assert_eq!(RegionStorage::for_world(&backups[0].1).load_chunk_data(&b).unwrap().unwrap(), b"new");
+		let _ = fs::remove_dir_all(path.parent().unwrap());
+	}
+	#[test]
+	fn restore_keeps_chunks_missing_from_backup() {
+		let path = temp_world_path("restore-metadata");
+		let world = World { name: "no chunks".into(), ..World::default() };
+		let old = fake_backup(&path, 10, "");
+		write_atomic(&old, |file| world.write(file)).unwrap();
+		fs::write(&path, "current").unwrap();
+		RegionStorage::for_world(&path).save_chunk(&ChunkCoord { x: 0, y: 0 }, b"chunk").unwrap();
+
+		restore(&path, &old, 5).unwrap();
+		assert_eq!(World::read(&mut File::open(&path).unwrap()).unwrap().name, "no chunks");
+		assert!(RegionStorage::for_world(&path).load_chunk_data(&ChunkCoord { x: 0, y: 0 }).unwrap().is_some());
+		let _ = fs::remove_dir_all(path.parent().unwrap());
+	}
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 /// A stack of an item.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub struct ItemStack {
     /// The size of the stack.
     pub stack: u16,
@@ -14,6 +14,8 @@ pub struct ItemStack {
 /// A chest.
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Chest {
+    /// The index of the chest in the world (clients refer to chests by it).
+    pub index: u16,
     /// The name of the chest.
     pub name: String,
     /// The X tile coordinate of the chest.
@@ -0,0 +1,204 @@
+use log::{debug, info, warn, error};
+use std::{
+	error::Error,
+	sync::Arc,
+	collections::{HashMap, HashSet},
+	time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+use super::{Chunk, ChunkAction, ChunkActionSender, ChunkContext, ChunkCoord, LoadedChunk, TileCoord};
+use crate::world::WorldStorage;
+use crate::server::ClientActionSender;
+
+/// Radius (in chunks) around spawn that is sent to clients when they join and never unloaded
+pub const SPAWN_AREA_RADIUS: u16 = 6;
+/// Radius (in chunks) around a player that is kept loaded & sent to them
+pub const VIEW_RADIUS: u16 = 6;
+/// Largest chunk size whose section always fits in a SendSection packet (tiles pack to at most 14 bytes, the rest is left for chests, signs & tile entities)
+pub const MAX_CHUNK_SIZE: u16 = 48;
+
+/// Chunks that have been sent to a player
+struct Viewer {
+	sender: ClientActionSender,
+	center: ChunkCoord,
+	sent: HashSet<ChunkCoord>,
+}
+
+/// Keeps chunks near players (and spawn) loaded from storage & assigned to the chunk thread
+pub struct ChunkManager {
+	chunks: HashMap<ChunkCoord, LoadedChunk>, // Resident chunks
+	storage: Arc<dyn WorldStorage>,
+	context: Arc<ChunkContext>,
+	chunk_size: u16,
+	spawn_chunk: ChunkCoord,
+	viewers: HashMap<usize, Viewer>, // Indexed by client id
+}
+impl ChunkManager {
+	pub fn new(storage: Arc<dyn WorldStorage>, context: Arc<ChunkContext>, chunk_size: u16, spawn_coord: &TileCoord) -> Result<Self, Box<dyn Error>> {
+		if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+			return Err(format!("Chunk size {} is not supported (1 to {} tiles)", chunk_size, MAX_CHUNK_SIZE).into())
+		}
+		Ok(ChunkManager {
+			chunks: HashMap::new(),
+			storage, context, chunk_size,
+			spawn_chunk: ChunkCoord::from_tilecoord(spawn_coord, chunk_size),
+			viewers: HashMap::new(),
+		})
+	}
+	/// Chunk coordinates within radius of center that are inside the world
+	fn area(&self, center: ChunkCoord, radius: u16) -> Vec<ChunkCoord> {
+		let world_size = &self.context.world_size;
+		let max_x = (world_size.x.max(1) - 1) / self.chunk_size;
+		let max_y = (world_size.y.max(1) - 1) / self.chunk_size;
+		let mut coords = vec![];
+		for y in center.y.saturating_sub(radius)..=center.y.saturating_add(radius).min(max_y) {
+			for x in center.x.saturating_sub(radius)..=center.x.saturating_add(radius).min(max_x) {
+				coords.push(ChunkCoord { x, y });
+			}
+		}
+		coords
+	}
+	fn in_spawn_area(&self, coord: &ChunkCoord) -> bool {
+		let (dx, dy) = ((coord.x as i32 - self.spawn_chunk.x as i32).abs(), (coord.y as i32 - self.spawn_chunk.y as i32).abs());
+		dx <= SPAWN_AREA_RADIUS as i32 && dy <= SPAWN_AREA_RADIUS as i32
+	}
+	/// Make sure chunk is resident, loading it from storage (or creating it) if needed
+	fn load(&mut self, coord: ChunkCoord) -> Result<&mut LoadedChunk, Box<dyn Error>> {
+		if !self.chunks.contains_key(&coord) {
+			let start_pos = TileCoord { x: coord.x * self.chunk_size, y: coord.y * self.chunk_size };
+			let chunk = match self.storage.load_chunk(&coord)? {
+				Some(chunk) => chunk,
+				// Worlds without stored tile data get a test chunk to spawn in
+				None if coord == self.spawn_chunk => Chunk { start_pos, ..Chunk::test_chunk(self.chunk_size) },
+				None => Chunk::new(start_pos, self.chunk_size),
+			};
+			self.chunks.insert(coord, LoadedChunk::from_chunk(chunk));
+		}
+		Ok(self.chunks.get_mut(&coord).unwrap())
+	}
+	/// Handle of the chunk thread that holds the loaded chunks
+	pub async fn handle(&mut self) -> Result<ChunkActionSender, Box<dyn Error>> {
+		let context = self.context.clone();
+		let spawn_chunk = self.spawn_chunk;
+		let loaded_chunk = self.load(spawn_chunk)?;
+		Ok(loaded_chunk.get_chunk_handle(&context).await?)
+	}
+	/// Load chunks & assign them to the chunk thread
+	async fn load_area(&mut self, coords: &[ChunkCoord]) -> Result<(), Box<dyn Error>> {
+		let mut handle = self.handle().await?;
+		let now = Instant::now();
+		for coord in coords {
+			let loaded_chunk = match self.load(*coord) {
+				Ok(loaded_chunk) => loaded_chunk,
+				Err(err) => { error!("Failed to load chunk at {:?}: {:?}", coord, err); continue },
+			};
+			loaded_chunk.last_seen = now;
+			if loaded_chunk.is_assigned() { continue; }
+			if let Err(err) = loaded_chunk.send_lock(&mut handle).await {
+				warn!("Failed to assign chunk at {:?} to chunk thread: {:?}", coord, err);
+			}
+		}
+		Ok(())
+	}
+	/// Send spawn area (or area around a tile) to a joining client
+	pub async fn request_sections(&mut self, id: usize, sender: ClientActionSender, center: Option<TileCoord>) -> Result<(), Box<dyn Error>> {
+		let mut center = center.map_or(self.spawn_chunk, |coord| ChunkCoord::from_tilecoord(&coord, self.chunk_size));
+		let mut coords = self.area(center, SPAWN_AREA_RADIUS);
+		if coords.is_empty() { // Requested spawn point is outside of the world
+			center = self.spawn_chunk;
+			coords = self.area(center, SPAWN_AREA_RADIUS);
+		}
+		self.load_area(&coords).await?;
+
+		let mut handle = self.handle().await?;
+		handle.send(ChunkAction::RequestSections(id, sender.clone(), coords.clone())).await?;
+		self.viewers.insert(id, Viewer { sender, center, sent: coords.into_iter().collect() });
+		Ok(())
+	}
+	/// Load & send chunks that came into view of a moving player
+	pub async fn player_moved(&mut self, id: usize, position: TileCoord) -> Result<(), Box<dyn Error>> {
+		let center = ChunkCoord::from_tilecoord(&position, self.chunk_size);
+		let new_coords: Vec<ChunkCoord> = match self.viewers.get(&id) {
+			Some(viewer) if viewer.center != center => {
+				self.area(center, VIEW_RADIUS).into_iter().filter(|coord| !viewer.sent.contains(coord)).collect()
+			},
+			_ => return Ok(()), // Same chunk as last time or client didn't request sections
+		};
+		self.load_area(&new_coords).await?;
+
+		let viewer = self.viewers.get_mut(&id).unwrap();
+		viewer.center = center;
+		viewer.sent.extend(new_coords.iter().copied());
+		if !new_coords.is_empty() {
+			let sender = viewer.sender.clone();
+			let mut handle = self.handle().await?;
+			handle.send(ChunkAction::SendChunks(id, sender, new_coords)).await?;
+		}
+		Ok(())
+	}
+	pub fn remove_player(&mut self, id: usize) {
+		self.viewers.remove(&id);
+	}
+	/// Save & unload chunks no player has been near for `delay` (spawn area stays loaded)
+	pub async fn unload_unobserved(&mut self, delay: Duration) -> Result<(), Box<dyn Error>> {
+		let now = Instant::now();
+		let mut observed = HashSet::new();
+		for viewer in self.viewers.values() {
+			observed.extend(self.area(viewer.center, VIEW_RADIUS));
+		}
+		for coord in observed.iter() {
+			if let Some(loaded_chunk) = self.chunks.get_mut(coord) { loaded_chunk.last_seen = now; }
+		}
+		let unload: Vec<ChunkCoord> = self.chunks.iter()
+			.filter(|(coord, loaded_chunk)| now.duration_since(loaded_chunk.last_seen) > delay && !self.in_spawn_area(coord))
+			.map(|(coord, _)| *coord).collect();
+		if unload.is_empty() { return Ok(()) }
+
+		for coord in unload.iter() {
+			let loaded_chunk = self.chunks.remove(coord).unwrap();
+			let (chunk, dirty) = loaded_chunk.unload(*coord, false).await;
+			if dirty { self.storage.save_chunk(coord, &chunk)?; }
+		}
+		for viewer in self.viewers.values_mut() {
+			for coord in unload.iter() { viewer.sent.remove(coord); }
+		}
+		debug!("Unloaded {} chunks, {} still loaded", unload.len(), self.chunks.len());
+		Ok(())
+	}
+	/// Compressed copies of every chunk modified since the last call
+	pub async fn collect_dirty(&mut self) -> Vec<(ChunkCoord, Vec<u8>)> {
+		let (sender, mut receiver) = mpsc::channel(self.chunks.len().max(1));
+		for (coord, loaded_chunk) in self.chunks.iter_mut() {
+			if let Err(err) = loaded_chunk.save_if_dirty(*coord, &sender).await {
+				warn!("Failed to save chunk at {:?}: {:?}", coord, err);
+			}
+		}
+		drop(sender); // Receiver finishes once every chunk thread has replied
+		let mut saved = Vec::new();
+		while let Some(chunk) = receiver.recv().await { saved.push(chunk); }
+		saved
+	}
+	/// Flag chunks as modified again after their saved copies failed to be written
+	pub fn mark_dirty(&mut self, coords: &[ChunkCoord]) {
+		for coord in coords {
+			match self.chunks.get(coord) {
+				Some(loaded_chunk) => loaded_chunk.mark_dirty(),
+				None => error!("Chunk at {:?} was unloaded before its failed save, its changes are lost", coord),
+			}
+		}
+	}
+	/// Close chunk thread and save every modified chunk
+	pub async fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+		let mut saved = 0;
+		for (coord, loaded_chunk) in self.chunks.drain().collect::<Vec<_>>() {
+			let (chunk, dirty) = loaded_chunk.unload(coord, true).await;
+			if dirty {
+				self.storage.save_chunk(&coord, &chunk)?;
+				saved += 1;
+			}
+		}
+		info!("Saved {} modified chunks", saved);
+		Ok(())
+	}
+}
@@ -1,22 +1,35 @@
 #![allow(dead_code)]
 
-use log::{trace, error};
-use std::{io::{self, Read, Write}, error::Error, sync::Arc};
+use log::{trace, warn, error};
+use std::{io::{self, Read, Write}, error::Error, sync::{Arc, atomic::{AtomicBool, Ordering}}, cmp::min, time::Instant};
 use serde::{Serialize, Deserialize};
 use tokio::sync::{mpsc, Mutex, OwnedMutexGuard};
 use arc_swap::ArcSwap;
 use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use byteorder::{LittleEndian, WriteBytesExt};
+use variant_encoding::VarStringWriter;
 
 pub mod tile;
 pub mod chest;
 pub mod sign;
-pub use tile::{TileCoord, Tile, Block};
+pub mod tile_entity;
+pub mod manager;
+pub use tile::{TileCoord, Tile, Block, TileType, TileEdit};
 pub use chest::Chest;
 pub use sign::Sign;
+pub use tile_entity::{TileEntity, TileEntityKind, TownRoom};
+pub use manager::ChunkManager;
 
-use crate::server::ClientActionSender;
+use super::format::{self, Header};
+use crate::server::{ClientAction, ClientActionSender, packet::{Packet, types::NetworkText}};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+/// Default width & height (in tiles) of a chunk
+pub const DEFAULT_CHUNK_SIZE: u16 = 16;
+/// Size of the sections vanilla clients frame tiles in
+pub const SECTION_WIDTH: u16 = 200;
+pub const SECTION_HEIGHT: u16 = 150;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub struct ChunkCoord {
 	pub x: u16,
 	pub y: u16,
@@ -27,25 +40,42 @@ impl ChunkCoord {
 	}
 }
 
+/// World data that every chunk thread needs read access to
+#[derive(Debug, Default)]
+pub struct ChunkContext {
+	pub world_size: TileCoord, // Width & Height of the world in tiles
+	pub tile_frame_important: Vec<bool>, // Whether frame data is sent for each block id
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Chunk {
 	pub start_pos: TileCoord,
 	pub chunk_size: u16,
 	
-	pub tiles: Vec<Tile>,
+	pub tiles: Vec<Tile>, // Stored row by row (index = y * chunk_size + x)
 	pub chests: Vec<Chest>,
-	pub signs: Vec<u8>,
-	pub tileentities: Vec<u8>,
+	pub signs: Vec<Sign>,
+	pub tile_entities: Vec<TileEntity>,
+	pub pressure_plates: Vec<TileCoord>, // Weighted pressure plates
+	pub town_rooms: Vec<TownRoom>, // Rooms (by the tile NPCs live at) the town manager assigned
 }
 
 impl Chunk {
+	/// Creates chunk filled with empty tiles
+	pub fn new(start_pos: TileCoord, chunk_size: u16) -> Chunk {
+		Chunk {
+			start_pos, chunk_size,
+			tiles: vec![Tile::default(); chunk_size as usize * chunk_size as usize],
+			..Chunk::default()
+		}
+	}
 	pub fn test_chunk(chunk_size: u16) -> Chunk {
-		let mut chunk = Chunk::default();
+		let mut chunk = Chunk { chunk_size, ..Chunk::default() };
 		chunk.tiles.reserve_exact( chunk_size as usize * chunk_size as usize);
 		for _ in 0..chunk_size {
 			for x in 0..chunk_size {
 				if x > chunk_size-3 {
-					chunk.tiles.push(Tile::new(Block::new(0)));
+					chunk.tiles.push(Tile::new(Block::new(TileType::Dirt)));
 				} else {
 					chunk.tiles.push(Tile::default())
 				}
@@ -53,43 +83,201 @@ impl Chunk {
 		}
 		chunk
 	}
+	/// Index into `tiles` of a world tile coordinate, None if coordinate is outside this chunk
+	pub fn tile_index(&self, coord: &TileCoord) -> Option<usize> {
+		if coord.x < self.start_pos.x || coord.y < self.start_pos.y { return None }
+		let (x, y) = (coord.x - self.start_pos.x, coord.y - self.start_pos.y);
+		if x >= self.chunk_size || y >= self.chunk_size { return None }
+		Some(y as usize * self.chunk_size as usize + x as usize)
+	}
+	pub fn get_tile(&self, coord: &TileCoord) -> Option<&Tile> {
+		self.tile_index(coord).and_then(move |i| self.tiles.get(i))
+	}
+	pub fn get_tile_mut(&mut self, coord: &TileCoord) -> Option<&mut Tile> {
+		self.tile_index(coord).and_then(move |i| self.tiles.get_mut(i))
+	}
+	/// Writes a rectangle of tiles (plus the chests & signs in it) in the vanilla section format, uncompressed.
+	/// Tiles outside this chunk are written as empty tiles.
+	pub fn write_section(&self, start: TileCoord, width: u16, height: u16, frame_important: &[bool], writer: &mut impl Write) -> Result<(), io::Error> {
+		writer.write_i32::<LittleEndian>(start.x as i32)?;
+		writer.write_i32::<LittleEndian>(start.y as i32)?;
+		writer.write_i16::<LittleEndian>(width as i16)?;
+		writer.write_i16::<LittleEndian>(height as i16)?;
+		
+		// Tiles are sent row by row, runs of identical tiles are sent once with a repeat count
+		let mut last: Option<(Tile, Vec<u8>, Vec<u8>)> = None;
+		let mut repeat = 0u16;
+		for y in start.y..start.y + height {
+			for x in start.x..start.x + width {
+				let tile = self.get_tile(&TileCoord { x, y }).copied().unwrap_or_default();
+				if let Some((last_tile, ..)) = &last {
+					if *last_tile == tile { repeat += 1; continue; }
+				}
+				if let Some((_, header, data)) = last.take() {
+					write_packed_tile(writer, header, &data, repeat)?;
+				}
+				let (header, data) = tile.pack(frame_important);
+				last = Some((tile, header, data));
+				repeat = 0;
+			}
+		}
+		if let Some((_, header, data)) = last.take() {
+			write_packed_tile(writer, header, &data, repeat)?;
+		}
+		
+		let in_section = |x: u32, y: u32| {
+			x >= start.x as u32 && x < start.x as u32 + width as u32 && y >= start.y as u32 && y < start.y as u32 + height as u32
+		};
+		let chests: Vec<&Chest> = self.chests.iter().filter(|chest| in_section(chest.x, chest.y)).collect();
+		writer.write_i16::<LittleEndian>(chests.len() as i16)?;
+		for chest in chests {
+			writer.write_i16::<LittleEndian>(chest.index as i16)?;
+			writer.write_i16::<LittleEndian>(chest.x as i16)?;
+			writer.write_i16::<LittleEndian>(chest.y as i16)?;
+			writer.write_varstring(&chest.name)?;
+		}
+		let signs: Vec<&Sign> = self.signs.iter().filter(|sign| in_section(sign.x, sign.y)).collect();
+		writer.write_i16::<LittleEndian>(signs.len() as i16)?;
+		for sign in signs {
+			writer.write_i16::<LittleEndian>(sign.index as i16)?;
+			writer.write_i16::<LittleEndian>(sign.x as i16)?;
+			writer.write_i16::<LittleEndian>(sign.y as i16)?;
+			writer.write_varstring(&sign.text)?;
+		}
+		let tile_entities: Vec<&TileEntity> = self.tile_entities.iter().filter(|entity| in_section(entity.x, entity.y)).collect();
+		writer.write_i16::<LittleEndian>(tile_entities.len() as i16)?;
+		for entity in tile_entities {
+			entity.write(writer)?;
+		}
+		Ok(())
+	}
+	/// Compressed section data of the whole chunk (clipped to the world's size) for SendSection packets
+	pub fn section(&self, context: &ChunkContext) -> Result<Vec<u8>, io::Error> {
+		let width = min(self.chunk_size, context.world_size.x.saturating_sub(self.start_pos.x));
+		let height = min(self.chunk_size, context.world_size.y.saturating_sub(self.start_pos.y));
+		
+		let mut writer = DeflateEncoder::new(Vec::new(), Compression::default());
+		self.write_section(self.start_pos, width, height, &context.tile_frame_important, &mut writer)?;
+		writer.finish()
+	}
 	pub fn read(reader: &mut impl Read) -> Result<Chunk, Box<dyn Error>> {
-		let mut reader = DeflateDecoder::new(reader);
+		let (header, mut reader) = Header::read(reader)?;
 		
-		trace!("Reading Chunk");
-		Ok(bincode::deserialize_from(&mut reader)?)
+		trace!("Reading Chunk (format version {})", header.version);
+		Ok(format::deserialize(&mut reader)?) // Chunks have always been written with a header, there are no older layouts
 	}
 	pub fn write(&self, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
-		trace!("Compressing World Data");
-		let mut writer = DeflateEncoder::new(writer, Compression::default());
-		
 		trace!("Writing Chunk");
-		bincode::serialize_into(&mut writer, self)?;
-		writer.finish()?;
-
+		format::write(writer, self)?;
 		Ok(())
 	}
 }
+/// Writes a packed tile, setting the repeat count flags in its first header byte
+pub(crate) fn write_packed_tile(writer: &mut impl Write, mut header: Vec<u8>, data: &[u8], repeat: u16) -> Result<(), io::Error> {
+	if repeat > 255 { header[0] |= 0b10000000; }
+	else if repeat > 0 { header[0] |= 0b01000000; }
+	writer.write_all(&header)?;
+	writer.write_all(data)?;
+	if repeat > 255 { writer.write_u16::<LittleEndian>(repeat)?; }
+	else if repeat > 0 { writer.write_u8(repeat as u8)?; }
+	Ok(())
+}
+
 #[derive(Debug)]
 pub enum ChunkAction {
-	AssignChunk(OwnedMutexGuard<Chunk>),
-	RequestSections(ClientActionSender), // Send cached chunk packets to client
+	AssignChunk(OwnedMutexGuard<Chunk>, Arc<AtomicBool>), // Chunk lock & its dirty flag
+	RequestSections(usize, ClientActionSender, Vec<ChunkCoord>), // Send chunks to joining client (with id) and add it to the client pool
+	SendChunks(usize, ClientActionSender, Vec<ChunkCoord>), // Send chunks a client (with id) moved close to
+	ReleaseChunk(ChunkCoord), // Drop lock on chunk so the world thread can unload it
 	
-	ModifyBlock(ClientActionSender, TileCoord),
+	ModifyBlock(usize, ClientActionSender, TileCoord, TileEdit), // Client (with id) edited a tile
+	SaveChunk(ChunkCoord, mpsc::Sender<(ChunkCoord, Vec<u8>)>), // Send back compressed copy of chunk for autosaving
+
 	ForceCloseThread(),
 }
 pub type ChunkActionSender = mpsc::Sender<ChunkAction>;
 
 /// Holds locks on multiple chunks and manages client block interactions
 pub struct ChunkThread {
-	client_pool: Vec<ClientActionSender>,
+	client_pool: Vec<(usize, ClientActionSender)>, // Clients viewing chunks held by this thread
 	chunks: Vec<OwnedMutexGuard<Chunk>>,
+	dirty: Vec<Arc<AtomicBool>>, // Set when chunk at the same index is modified (shared with its LoadedChunk)
+	sections: Vec<Option<ArcSwap<Vec<u8>>>>, // Cached section data for each chunk (None if it needs to be regenerated)
+	context: Arc<ChunkContext>,
 }
 impl ChunkThread {
-	pub fn new(initial_chunk: OwnedMutexGuard<Chunk>) -> Self {
+	pub fn new(initial_chunk: OwnedMutexGuard<Chunk>, dirty: Arc<AtomicBool>, context: Arc<ChunkContext>) -> Self {
 		ChunkThread {
 			client_pool: Default::default(),
 			chunks: vec![initial_chunk],
+			dirty: vec![dirty],
+			sections: vec![None],
+			context,
+		}
+	}
+	/// Get section data for chunk at index, generating it if not cached
+	fn get_section(&mut self, index: usize) -> Result<ArcSwap<Vec<u8>>, io::Error> {
+		if let Some(section) = &self.sections[index] { return Ok(section.clone()) }
+		let section = ArcSwap::new(Arc::new(self.chunks[index].section(&self.context)?));
+		self.sections[index] = Some(section.clone());
+		Ok(section)
+	}
+	/// Send packet to every client in the pool (except one id), removing clients that have disconnected
+	async fn broadcast(&mut self, packet: &Packet, except: Option<usize>) {
+		let mut disconnected = vec![];
+		for (id, sender) in self.client_pool.iter_mut() {
+			if Some(*id) == except { continue; }
+			if sender.send(ClientAction::SendPacket(packet.clone())).await.is_err() {
+				disconnected.push(*id);
+			}
+		}
+		self.client_pool.retain(|(id, _)| !disconnected.contains(id));
+	}
+	/// Validate and apply tile edit, returns index of the modified chunk
+	fn modify_tile(&mut self, coord: &TileCoord, edit: &TileEdit) -> Result<usize, &'static str> {
+		if coord.x >= self.context.world_size.x || coord.y >= self.context.world_size.y { return Err("Tile is outside of world") }
+		let index = self.chunks.iter().position(|chunk| chunk.tile_index(coord).is_some()).ok_or("Tile is not in a chunk held by this thread")?;
+		let tile = self.chunks[index].get_tile_mut(coord).ok_or("Tile is not in chunk")?;
+		let before = *tile;
+		if !tile.apply(edit, &self.context.tile_frame_important) { return Err("Edit is not valid for tile") }
+		if *tile != before { // Relayed edits & hits that only damage a tile leave it as it was
+			self.sections[index] = None; // Regenerate section next time it is requested
+			self.dirty[index].store(true, Ordering::Release);
+		}
+		Ok(index)
+	}
+	/// Index of chunk at coord in this thread
+	fn chunk_index(&self, coord: &ChunkCoord) -> Option<usize> {
+		self.chunks.iter().position(|chunk| ChunkCoord::from_tilecoord(&chunk.start_pos, chunk.chunk_size) == *coord)
+	}
+	/// Section packets for chunks at coords (that are held by this thread) followed by the TileFrameSection covering them
+	fn section_packets(&mut self, coords: &[ChunkCoord]) -> Result<Vec<Packet>, io::Error> {
+		let indices: Vec<usize> = coords.iter().filter_map(|coord| self.chunk_index(coord)).collect();
+		let mut packets = Vec::with_capacity(indices.len() + 1);
+		for i in indices.iter() {
+			packets.push(Packet::WorldChunk(self.get_section(*i)?));
+		}
+		if !indices.is_empty() { packets.push(self.frame_section(&indices)); }
+		Ok(packets)
+	}
+	/// Add client to pool (if not already in it) so it receives tile changes
+	fn add_client(&mut self, id: usize, sender: &ClientActionSender) {
+		self.client_pool.retain(|(pool_id, _)| *pool_id != id);
+		self.client_pool.push((id, sender.clone()));
+	}
+	/// TileFrameSection packet covering chunks at indices
+	fn frame_section(&self, indices: &[usize]) -> Packet {
+		let world_size = &self.context.world_size;
+		let (mut start, mut end) = (TileCoord { x: u16::MAX, y: u16::MAX }, TileCoord::default());
+		for chunk in indices.iter().map(|i| &self.chunks[*i]) {
+			start.x = min(start.x, chunk.start_pos.x);
+			start.y = min(start.y, chunk.start_pos.y);
+			end.x = end.x.max(min(chunk.start_pos.x + chunk.chunk_size, world_size.x).saturating_sub(1));
+			end.y = end.y.max(min(chunk.start_pos.y + chunk.chunk_size, world_size.y).saturating_sub(1));
+		}
+		Packet::TileFrameSection {
+			start_x: (start.x / SECTION_WIDTH) as i16, start_y: (start.y / SECTION_HEIGHT) as i16,
+			end_x: (end.x / SECTION_WIDTH) as i16, end_y: (end.y / SECTION_HEIGHT) as i16,
 		}
 	}
 	pub async fn handle(&mut self, mut action_receiver: mpsc::Receiver<ChunkAction>) -> Result<(), Box<dyn Error>> {
@@ -98,50 +286,95 @@ impl ChunkThread {
 			if let Some(action) = result {
 				use ChunkAction::*;
 				match action {
-					RequestSections(_sender) => {
-						// Send cached chunk data to client (generate if needed)
+					RequestSections(id, mut sender, coords) => {
+						self.add_client(id, &sender);
+						
+						// Send cached chunk data to client (generate if needed), client advances its loading bar for every section
+						let mut packets = vec![Packet::Status(coords.len() as i32, NetworkText::localized("LegacyInterface.44"), 0)];
+						packets.append(&mut self.section_packets(&coords)?);
+						
+						let mut actions: Vec<ClientAction> = packets.into_iter().map(ClientAction::SendPacket).collect();
+						actions.push(ClientAction::SectionsSent);
+						for action in actions {
+							if sender.send(action).await.is_err() {
+								warn!("Client disconnected while receiving sections");
+								break;
+							}
+						}
 					},
-					AssignChunk(chunk_lock) => { // Assigns a loaded chunk to this Chunk Thread (managed by World thread)
+					SendChunks(id, mut sender, coords) => {
+						self.add_client(id, &sender);
+						for packet in self.section_packets(&coords)? {
+							if sender.send(ClientAction::SendPacket(packet)).await.is_err() { break }
+						}
+					},
+					ReleaseChunk(coord) => {
+						if let Some(index) = self.chunk_index(&coord) {
+							self.chunks.remove(index); // Drops lock
+							self.dirty.remove(index);
+							self.sections.remove(index);
+						}
+					},
+					AssignChunk(chunk_lock, dirty) => { // Assigns a loaded chunk to this Chunk Thread (managed by World thread)
 						self.chunks.push(chunk_lock);
+						self.dirty.push(dirty);
+						self.sections.push(None);
 					},
-					ModifyBlock(_sender, _tile_coord) => {
-						
+					ModifyBlock(id, mut sender, coord, edit) => {
+						match self.modify_tile(&coord, &edit) {
+							Ok(_) => self.broadcast(&Packet::TileManipulation{coord, edit}, Some(id)).await,
+							Err(reason) => {
+								warn!("Rejected {:?} at {:?} from client {}: {}", edit, coord, id, reason);
+								// Resync client with the server's copy of the chunk
+								if let Some(index) = self.chunks.iter().position(|chunk| chunk.tile_index(&coord).is_some()) {
+									let section = self.get_section(index)?;
+									let _ = sender.send(ClientAction::SendPacket(Packet::WorldChunk(section))).await;
+								}
+							},
+						}
+					},
+					SaveChunk(coord, mut sender) => {
+						if let Some(chunk) = self.chunk_index(&coord).map(|index| &self.chunks[index]) {
+							let mut data = Vec::new();
+							chunk.write(&mut data)?;
+							let _ = sender.send((coord, data)).await; // World thread may have stopped waiting
+						} else { warn!("Asked to save chunk {:?} that isn't held by this thread", coord); }
 					},
 					ForceCloseThread() => break,
 				}
-			}
+			} else { break } // All senders dropped
 		}
 		Ok(())
 	}
 }
 
 /// Object that stores loaded chunk mutex
-#[derive(Default)]
 pub struct LoadedChunk {
 	chunk: Arc<Mutex<Chunk>>, // Contains Chunk Mutex if chunk is loaded
 	action: Option<ChunkActionSender>, // Contains action channel to chunk thread that has lock on this chunk
+	dirty: Arc<AtomicBool>, // Whether chunk was modified since it was last saved
+	pub last_seen: Instant, // Last time a player was close to this chunk
 }
 impl LoadedChunk {
-	pub fn from_file(reader: impl io::BufRead) -> Result<Self, Box<dyn Error>> {
-		let chunk = bincode::deserialize_from(reader)?;
-		Ok(LoadedChunk {
-			chunk: Arc::new(Mutex::new(chunk)),
-			action: None,
-		})
+	pub fn from_file(mut reader: impl io::BufRead) -> Result<Self, Box<dyn Error>> {
+		let chunk = Chunk::read(&mut reader)?;
+		Ok(LoadedChunk::from_chunk(chunk))
 	}
 	pub fn from_chunk(source: Chunk) -> Self {
 		LoadedChunk {
 			chunk: Arc::new(Mutex::new(source)),
 			action: None,
+			dirty: Default::default(),
+			last_seen: Instant::now(),
 		}
 	}
-	pub async fn get_chunk_handle(&mut self) -> Result<ChunkActionSender, tokio::sync::TryLockError> {
+	pub async fn get_chunk_handle(&mut self, context: &Arc<ChunkContext>) -> Result<ChunkActionSender, tokio::sync::TryLockError> {
 		if let Some(action) = &self.action {
 			Ok(action.clone())
 		} else {
 			let arc = self.chunk.clone();
 			let lock = arc.try_lock_owned()?;
-			let mut thread = ChunkThread::new(lock);
+			let mut thread = ChunkThread::new(lock, self.dirty.clone(), context.clone());
 			let (tx, rx) = mpsc::channel::<ChunkAction>(100);
 			
 			tokio::spawn(async move {
@@ -153,11 +386,162 @@ impl LoadedChunk {
 			Ok(tx)
 		}
 	}
+	/// Whether a chunk thread has the lock on this chunk
+	pub fn is_assigned(&self) -> bool {
+		self.action.is_some()
+	}
+	/// Get compressed copy of chunk if it was modified since the last call (asking its chunk thread if it holds the lock)
+	pub async fn save_if_dirty(&mut self, coord: ChunkCoord, sender: &mpsc::Sender<(ChunkCoord, Vec<u8>)>) -> Result<(), Box<dyn Error>> {
+		if !self.dirty.swap(false, Ordering::AcqRel) { return Ok(()) }
+		let result: Result<(), Box<dyn Error>> = async {
+			if let Some(action) = &mut self.action {
+				action.send(ChunkAction::SaveChunk(coord, sender.clone())).await?;
+			} else {
+				let mut data = Vec::new();
+				self.chunk.lock().await.write(&mut data)?;
+				sender.clone().send((coord, data)).await?;
+			}
+			Ok(())
+		}.await;
+		if result.is_err() { self.mark_dirty(); } // Nothing was saved, keep the changes for the next save
+		result
+	}
+	/// Flag chunk as modified again (e.g. after the write of its saved copy failed)
+	pub fn mark_dirty(&self) {
+		self.dirty.store(true, Ordering::Release);
+	}
+	/// Take chunk back from the chunk thread holding it (closing the thread if `close_thread`), returns whether it was modified since its last save
+	pub async fn unload(self, coord: ChunkCoord, close_thread: bool) -> (Chunk, bool) {
+		if let Some(mut action) = self.action {
+			let action_type = if close_thread { ChunkAction::ForceCloseThread() } else { ChunkAction::ReleaseChunk(coord) };
+			let _ = action.send(action_type).await; // Thread may have already closed
+		}
+		let mut chunk = self.chunk.lock().await; // Lock is released once the chunk thread drops it
+		(std::mem::take(&mut *chunk), self.dirty.load(Ordering::Acquire))
+	}
 	pub async fn send_lock(&mut self, sender: &mut ChunkActionSender) -> Result<(), Box<dyn Error>> {
 		let arc = self.chunk.clone();
 		let lock = arc.try_lock_owned()?;
 		self.action = Some(sender.clone());
-		sender.send(ChunkAction::AssignChunk(lock)).await?;
+		sender.send(ChunkAction::AssignChunk(lock, self.dirty.clone())).await?;
 		Ok(())
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use byteorder::ReadBytesExt;
+	use crate::enum_primitive::FromPrimitive;
+	use crate::data;
+	use tile::{Wall, WallType, Liquid};
+
+	/// Reads packed tiles back the way clients do (header flags, data, then the repeat count)
+	fn unpack_tiles(reader: &mut impl Read, count: usize, frame_important: &[bool]) -> Vec<Tile> {
+		let mut tiles = Vec::with_capacity(count);
+		while tiles.len() < count {
+			let flags1 = reader.read_u8().unwrap();
+			let flags2 = if flags1 & 0b00000001 != 0 { reader.read_u8().unwrap() } else { 0 };
+			let flags3 = if flags2 & 0b00000001 != 0 { reader.read_u8().unwrap() } else { 0 };
+			let mut tile = Tile::default();
+			if flags1 & 0b00000010 != 0 {
+				let mut id = reader.read_u8().unwrap() as u16;
+				if flags1 & 0b00100000 != 0 { id |= (reader.read_u8().unwrap() as u16) << 8; }
+				let mut block = Block { id, ..Block::default() };
+				if frame_important[id as usize] {
+					block.frame_width = reader.read_u16::<LittleEndian>().unwrap();
+					block.frame_height = reader.read_u16::<LittleEndian>().unwrap();
+				}
+				if flags3 & 0b00001000 != 0 { block.color = reader.read_u8().unwrap(); }
+				block.slope = (flags2 & 0b01110000) >> 4;
+				block.is_inactive = flags3 & 0b00000100 != 0;
+				tile.block = Some(block);
+			}
+			let mut wall = None;
+			if flags1 & 0b00000100 != 0 {
+				let id = reader.read_u8().unwrap() as u16;
+				let color = if flags3 & 0b00010000 != 0 { reader.read_u8().unwrap() } else { 0 };
+				wall = Some((id, color));
+			}
+			if flags1 & 0b00011000 != 0 {
+				tile.liquid = Some(Liquid { id: (flags1 & 0b00011000) >> 3, amount: reader.read_u8().unwrap() });
+			}
+			tile.has_wire = [flags2 & 0b00000010 != 0, flags2 & 0b00000100 != 0, flags2 & 0b00001000 != 0, flags3 & 0b00100000 != 0];
+			tile.has_actuator = flags3 & 0b00000010 != 0;
+			if let Some((mut id, color)) = wall {
+				if flags3 & 0b01000000 != 0 { id |= (reader.read_u8().unwrap() as u16) << 8; }
+				tile.wall = Some(Wall { id, color });
+			}
+			let repeat = if flags1 & 0b10000000 != 0 { reader.read_u16::<LittleEndian>().unwrap() }
+				else if flags1 & 0b01000000 != 0 { reader.read_u8().unwrap() as u16 }
+				else { 0 };
+			for _ in 0..=repeat { tiles.push(tile); }
+		}
+		tiles
+	}
+
+	#[test]
+	fn pack_sets_flag_bytes() {
+		let frame_important = data::tiles().frame_important();
+		// Only flags1 is sent when the other bytes are empty
+		assert_eq!(Tile::new(Block::new(TileType::Stone)).pack(&frame_important), (vec![0b00000010], vec![1]));
+		// Ids above 255 set the 2-byte id flags
+		let tile = Tile { wall: Some(Wall::new(WallType::BambooFence)), ..Tile::new(Block::new(TileType::BoneWelder)) };
+		let (header, data) = tile.pack(&frame_important);
+		assert_eq!(header, vec![0b00100111, 0b00000001, 0b01000000]);
+		assert_eq!(data, vec![300u16 as u8, 1, 0, 0, 0, 0, 315u16 as u8, 1]);
+		// flags3 alone still needs flags2 to announce it
+		let tile = Tile { has_actuator: true, ..Tile::default() };
+		assert_eq!(tile.pack(&frame_important).0, vec![0b00000001, 0b00000001, 0b00000010]);
+	}
+	#[test]
+	fn section_round_trip() {
+		let frame_important = data::tiles().frame_important();
+		let mut chunk = Chunk::new(TileCoord { x: 40, y: 60 }, 20);
+		// 300 empty tiles are a run with a 2-byte repeat count, then a run of 3 with a 1-byte count
+		for i in 300..303 {
+			chunk.tiles[i] = Tile { has_wire: [true, false, false, true], ..Tile::new(Block { color: 5, slope: 1, ..Block::new(TileType::Stone) }) };
+		}
+		chunk.tiles[303] = Tile::new(Block { frame_width: 18, frame_height: 36, is_inactive: true, ..Block::new(TileType::BoneWelder) });
+		chunk.tiles[303].has_actuator = true;
+		chunk.tiles[304].wall = Some(Wall { color: 2, ..Wall::new(WallType::BambooFence) });
+		chunk.tiles[305] = Tile { wall: Some(Wall::new(WallType::GrayBrick)), liquid: Some(Liquid { id: 2, amount: 255 }), ..Tile::default() };
+
+		let mut data = Vec::new();
+		chunk.write_section(chunk.start_pos, 20, 20, &frame_important, &mut data).unwrap();
+		let mut reader = &data[..];
+		assert_eq!(reader.read_i32::<LittleEndian>().unwrap(), 40);
+		assert_eq!(reader.read_i32::<LittleEndian>().unwrap(), 60);
+		assert_eq!(reader.read_i16::<LittleEndian>().unwrap(), 20);
+		assert_eq!(reader.read_i16::<LittleEndian>().unwrap(), 20);
+		assert_eq!(reader[0], 0b10000000); // Empty tile with a 2-byte repeat count
+		assert_eq!(unpack_tiles(&mut reader, 400, &frame_important), chunk.tiles);
+		assert_eq!(reader, [0u8; 6]); // No chests, signs or tile entities
+	}
+	#[tokio::test]
+	async fn only_changes_mark_chunk_dirty() {
+		let chunk = Arc::new(Mutex::new(Chunk::new(TileCoord::default(), 16))).lock_owned().await;
+		let dirty = Arc::new(AtomicBool::new(false));
+		let context = Arc::new(ChunkContext { world_size: TileCoord { x: 16, y: 16 }, ..ChunkContext::default() });
+		let mut thread = ChunkThread::new(chunk, dirty.clone(), context);
+		let coord = TileCoord { x: 3, y: 4 };
+		thread.get_section(0).unwrap();
+
+		thread.modify_tile(&coord, &TileEdit::Other{action: 18, var1: 0, var2: 0}).unwrap();
+		assert!(!dirty.load(Ordering::Acquire));
+		assert!(thread.sections[0].is_some());
+
+		thread.modify_tile(&coord, &TileEdit::PlaceWall{id: 4}).unwrap();
+		assert!(dirty.load(Ordering::Acquire));
+		assert!(thread.sections[0].is_none());
+	}
+	#[tokio::test]
+	async fn failed_save_keeps_chunk_dirty() {
+		let mut loaded_chunk = LoadedChunk::from_chunk(Chunk::new(TileCoord::default(), 16));
+		loaded_chunk.mark_dirty();
+		let (sender, receiver) = mpsc::channel(1);
+		drop(receiver);
+		assert!(loaded_chunk.save_if_dirty(ChunkCoord { x: 0, y: 0 }, &sender).await.is_err());
+		assert!(loaded_chunk.dirty.load(Ordering::Acquire));
+	}
+}
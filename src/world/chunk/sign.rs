@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 /// A sign.
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Sign {
+    /// The index of the sign in the world (clients refer to signs by it).
+    pub index: u16,
     /// The X tile coordinate of the sign.
     pub x: u32,
     /// The Y tile coordinate of the sign.
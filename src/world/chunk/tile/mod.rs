@@ -3,7 +3,11 @@ use serde::{Serialize, Deserialize};
 use std::io;
 
 use byteorder::{WriteBytesExt, ReadBytesExt, LittleEndian, ByteOrder};
+use crate::enum_primitive::FromPrimitive;
+use crate::data;
+pub mod block;
 pub mod wall;
+pub use block::{Block, TileType};
 pub use wall::{Wall, WallType};
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
@@ -26,7 +30,7 @@ impl TileCoord {
 }
 
 /// A tile in a World.
-#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct Tile {
 	/// The [Block](struct.Block.html) of the tile.
 	pub block: Option<Block>,
@@ -46,35 +50,303 @@ impl Tile {
 			..Tile::default()
 		}
 	}
+	/// Applies an edit to this tile, returns false if the edit isn't valid for this tile.
+	/// Objects with frames (furniture, plants...) can span multiple tiles, so placing, replacing or breaking them is rejected
+	/// instead of storing part of an object (TODO: place & kill whole objects once the registry has their sizes).
+	pub fn apply(&mut self, edit: &TileEdit, frame_important: &[bool]) -> bool {
+		use TileEdit::*;
+		let has_frames = |id: u16| is_frame_important(frame_important, id);
+		match *edit {
+			KillTile{fail} | KillTileNoItem{fail} => match &self.block {
+				Some(_) if fail => {}, // Only damaged
+				Some(block) if !has_frames(block.id) => self.block = None,
+				_ => return false,
+			},
+			PlaceTile{id, ..} => {
+				if self.block.is_some() || has_frames(id) { return false }
+				match TileType::from_u16(id) {
+					Some(id) => self.block = Some(Block::new(id)),
+					None => return false,
+				}
+			},
+			ReplaceTile{id, ..} => match &mut self.block {
+				Some(block) if !has_frames(block.id) && !has_frames(id) => match TileType::from_u16(id) {
+					Some(id) => *block = Block { color: block.color, ..Block::new(id) },
+					None => return false,
+				},
+				_ => return false,
+			},
+			KillWall{fail} => {
+				if self.wall.is_none() { return false }
+				if !fail { self.wall = None; }
+			},
+			PlaceWall{id} | ReplaceWall{id} => {
+				if self.wall.is_some() != matches!(edit, ReplaceWall{..}) { return false }
+				match WallType::from_u16(id) {
+					Some(id) => self.wall = Some(Wall { color: self.wall.map(|wall| wall.color).unwrap_or(0), ..Wall::new(id) }),
+					None => return false,
+				}
+			},
+			PlaceWire(color) | KillWire(color) => {
+				let wire = match self.has_wire.get_mut(color as usize) { Some(wire) => wire, None => return false };
+				let place = matches!(edit, PlaceWire(_));
+				if *wire == place { return false }
+				*wire = place;
+			},
+			PlaceActuator | KillActuator => {
+				let place = *edit == PlaceActuator;
+				if self.has_actuator == place { return false }
+				self.has_actuator = place;
+			},
+			// Objects with frames can't be hammered into shape
+			PoundTile => match &mut self.block {
+				Some(block) if !has_frames(block.id) => block.slope = if block.slope == 1 { 0 } else { 1 },
+				_ => return false,
+			},
+			SlopeTile(slope) => match &mut self.block {
+				Some(block) if slope <= 4 && !has_frames(block.id) => block.slope = if slope == 0 { 0 } else { slope + 1 }, // Stored after half brick
+				_ => return false,
+			},
+			Actuate => match &mut self.block {
+				Some(block) if self.has_actuator => block.is_inactive = !block.is_inactive,
+				_ => return false,
+			},
+			Other{..} => {},
+		}
+		true
+	}
+	/// Packs tile into the vanilla section format (used by SendSection packets).
+	/// Returns the header flags (flags1 first, empty trailing flags omitted) and the data that follows them.
+	/// Repeat count flags & bytes are left to the caller.
+	pub fn pack(&self, frame_important: &[bool]) -> (Vec<u8>, Vec<u8>) {
+		let mut flags = [0u8; 3];
+		let mut data = Vec::with_capacity(13);
+		
+		if let Some(block) = &self.block {
+			flags[0] |= 0b00000010;
+			let id = block.id;
+			data.push(id as u8);
+			if id > 255 {
+				flags[0] |= 0b00100000;
+				data.push((id >> 8) as u8);
+			}
+			if is_frame_important(frame_important, id) {
+				data.extend_from_slice(&block.frame_width.to_le_bytes());
+				data.extend_from_slice(&block.frame_height.to_le_bytes());
+			}
+			if block.color != 0 {
+				flags[2] |= 0b00001000;
+				data.push(block.color);
+			}
+			flags[1] |= (block.slope & 0b111) << 4; // Half brick = 1, slopes = 2..=5
+			if block.is_inactive { flags[2] |= 0b00000100; }
+		}
+		let wall_id = self.wall.map(|wall| wall.id).unwrap_or(0);
+		if let Some(wall) = self.wall.filter(|_| wall_id != 0) {
+			flags[0] |= 0b00000100;
+			data.push(wall_id as u8);
+			if wall.color != 0 {
+				flags[2] |= 0b00010000;
+				data.push(wall.color);
+			}
+		}
+		if let Some(liquid) = self.liquid.filter(|liquid| liquid.amount != 0) {
+			flags[0] |= (liquid.id & 0b11) << 3; // Water = 1, Lava = 2, Honey = 3
+			data.push(liquid.amount);
+		}
+		if self.has_wire[0] { flags[1] |= 0b00000010; }
+		if self.has_wire[1] { flags[1] |= 0b00000100; }
+		if self.has_wire[2] { flags[1] |= 0b00001000; }
+		if self.has_wire[3] { flags[2] |= 0b00100000; }
+		if self.has_actuator { flags[2] |= 0b00000010; }
+		if wall_id > 255 {
+			flags[2] |= 0b01000000;
+			data.push((wall_id >> 8) as u8);
+		}
+		
+		// Each flag byte is only sent if it (or a later one) has something set
+		let mut header = vec![flags[0]];
+		if flags[2] != 0 { flags[1] |= 0b00000001; }
+		if flags[1] != 0 {
+			header[0] |= 0b00000001;
+			header.push(flags[1]);
+			if flags[2] != 0 { header.push(flags[2]); }
+		}
+		(header, data)
+	}
+}
+
+/// Whether frames are saved & sent for a tile id, ids past the world's list fall back to the tile registry
+fn is_frame_important(frame_important: &[bool], id: u16) -> bool {
+	frame_important.get(id as usize).copied().unwrap_or_else(|| data::tiles().is_frame_important(id))
 }
 
-/// A foreground tile.
-#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
-pub struct Block {
-	/// The ID of the block.
-	pub id: u16,
-	/// The color of the block.
-	pub color: u8,
-	/// The width of the block frame.
-	pub frame_width: u16,
-	/// The height of the block frame.
-	pub frame_height: u16,
-	/// The slope of the block.
-	pub slope: u8,
-	/// True if the block is inactive.
-	pub is_inactive: bool,
+/// An edit a client made to a tile (sent in TileManipulation packets).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileEdit {
+	KillTile{fail: bool}, // Tile is only damaged if fail is set
+	PlaceTile{id: u16, style: u8},
+	KillWall{fail: bool},
+	PlaceWall{id: u16},
+	KillTileNoItem{fail: bool},
+	PlaceWire(u8), // Wire color (0 = Red, 1 = Blue, 2 = Green, 3 = Yellow)
+	KillWire(u8),
+	PoundTile, // Toggle half brick
+	PlaceActuator,
+	KillActuator,
+	SlopeTile(u8), // 0 = No slope, 1..=4 = Slopes
+	ReplaceTile{id: u16, style: u8},
+	ReplaceWall{id: u16},
+	Actuate, // Toggle inactive
+	Other{action: u8, var1: i16, var2: u8}, // Edits that are relayed but not applied by the server
 }
-impl Block {
-	pub fn new(id: u16) -> Block {
-		Block {id, ..Block::default()}
+impl TileEdit {
+	/// Construct from TileManipulation packet fields
+	pub fn from_raw(action: u8, var1: i16, var2: u8) -> TileEdit {
+		use TileEdit::*;
+		match action {
+			0 => KillTile{fail: var1 == 1},
+			1 => PlaceTile{id: var1 as u16, style: var2},
+			2 => KillWall{fail: var1 == 1},
+			3 => PlaceWall{id: var1 as u16},
+			4 => KillTileNoItem{fail: var1 == 1},
+			5 => PlaceWire(0), 6 => KillWire(0),
+			7 => PoundTile,
+			8 => PlaceActuator, 9 => KillActuator,
+			10 => PlaceWire(1), 11 => KillWire(1),
+			12 => PlaceWire(2), 13 => KillWire(2),
+			14 | 23 => SlopeTile(var1 as u8),
+			16 => PlaceWire(3), 17 => KillWire(3),
+			19 => Actuate,
+			21 => ReplaceTile{id: var1 as u16, style: var2},
+			22 => ReplaceWall{id: var1 as u16},
+			_ => Other{action, var1, var2},
+		}
+	}
+	/// TileManipulation packet fields (action, var1, var2)
+	pub fn to_raw(self) -> (u8, i16, u8) {
+		use TileEdit::*;
+		match self {
+			KillTile{fail} => (0, fail as i16, 0),
+			PlaceTile{id, style} => (1, id as i16, style),
+			KillWall{fail} => (2, fail as i16, 0),
+			PlaceWall{id} => (3, id as i16, 0),
+			KillTileNoItem{fail} => (4, fail as i16, 0),
+			PlaceWire(color) => ([5, 10, 12, 16][color as usize & 0b11], 0, 0),
+			KillWire(color) => ([6, 11, 13, 17][color as usize & 0b11], 0, 0),
+			PoundTile => (7, 0, 0),
+			PlaceActuator => (8, 0, 0),
+			KillActuator => (9, 0, 0),
+			SlopeTile(slope) => (14, slope as i16, 0),
+			Actuate => (19, 0, 0),
+			ReplaceTile{id, style} => (21, id as i16, style),
+			ReplaceWall{id} => (22, id as i16, 0),
+			Other{action, var1, var2} => (action, var1, var2),
+		}
 	}
 }
 
 /// A liquid tile.
-#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct Liquid {
 	/// The ID of the liquid.
 	pub id: u8,
 	/// The amount of the liquid.
 	pub amount: u8,
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn raw_edits_round_trip() {
+		for action in 0..=25u8 {
+			let edit = TileEdit::from_raw(action, 1, 3);
+			if action == 23 { continue }
+			assert_eq!(edit.to_raw(), match edit {
+				TileEdit::PlaceTile{..} | TileEdit::ReplaceTile{..} | TileEdit::Other{..} => (action, 1, 3),
+				TileEdit::PlaceWall{..} | TileEdit::ReplaceWall{..} | TileEdit::KillTile{..} | TileEdit::KillWall{..} | TileEdit::KillTileNoItem{..} | TileEdit::SlopeTile(_) => (action, 1, 0),
+				_ => (action, 0, 0),
+			}, "action {}", action);
+		}
+		// Slope & pound (23) is applied as a slope and relayed as one
+		assert_eq!(TileEdit::from_raw(23, 2, 0), TileEdit::SlopeTile(2));
+		assert_eq!(TileEdit::from_raw(23, 2, 0).to_raw(), (14, 2, 0));
+		assert_eq!(TileEdit::from_raw(15, -4, 9), TileEdit::Other{action: 15, var1: -4, var2: 9});
+	}
+	#[test]
+	fn apply_rejects_invalid_edits() {
+		let mut empty = Tile::default();
+		assert!(!empty.apply(&TileEdit::KillTile{fail: false}, &[]));
+		assert!(!empty.apply(&TileEdit::ReplaceTile{id: 1, style: 0}, &[]));
+		assert!(!empty.apply(&TileEdit::PoundTile, &[]));
+		assert!(!empty.apply(&TileEdit::ReplaceWall{id: 1}, &[]));
+		assert!(!empty.apply(&TileEdit::PlaceTile{id: 10_000, style: 0}, &[]));
+		assert!(!empty.apply(&TileEdit::PlaceWall{id: 10_000}, &[]));
+		assert!(!empty.apply(&TileEdit::PlaceWire(4), &[]));
+		assert!(!empty.apply(&TileEdit::KillWire(0), &[]));
+		assert!(!empty.apply(&TileEdit::Actuate, &[]));
+		assert_eq!(empty, Tile::default());
+
+		let mut tile = Tile::new(Block::new(TileType::Stone));
+		assert!(!tile.apply(&TileEdit::PlaceTile{id: 1, style: 0}, &[]));
+		assert!(!tile.apply(&TileEdit::SlopeTile(5), &[]));
+		let mut furniture = Tile::new(Block::new(TileType::BoneWelder));
+		assert!(!furniture.apply(&TileEdit::PoundTile, &[]));
+		assert!(!furniture.apply(&TileEdit::SlopeTile(1), &[]));
+	}
+	#[test]
+	fn apply_rejects_partial_objects() {
+		let mut frame_important = vec![false; TileType::BoneWelder as usize + 1];
+		frame_important[TileType::Containers as usize] = true; // The world's list overrides the registry
+		frame_important[TileType::BoneWelder as usize] = true;
+
+		let mut empty = Tile::default();
+		assert!(!empty.apply(&TileEdit::PlaceTile{id: TileType::Containers as u16, style: 0}, &frame_important));
+		assert!(!empty.apply(&TileEdit::PlaceTile{id: TileType::BoneWelder as u16, style: 0}, &[]));
+		assert_eq!(empty, Tile::default());
+
+		let mut furniture = Tile::new(Block { frame_width: 18, ..Block::new(TileType::Containers) });
+		let before = furniture;
+		assert!(!furniture.apply(&TileEdit::KillTile{fail: false}, &frame_important));
+		assert!(!furniture.apply(&TileEdit::KillTileNoItem{fail: false}, &frame_important));
+		assert!(!furniture.apply(&TileEdit::ReplaceTile{id: TileType::Stone as u16, style: 0}, &frame_important));
+		assert!(furniture.apply(&TileEdit::KillTile{fail: true}, &frame_important)); // Hits that only damage it are fine
+		assert_eq!(furniture, before);
+
+		let mut stone = Tile::new(Block::new(TileType::Stone));
+		assert!(!stone.apply(&TileEdit::ReplaceTile{id: TileType::Containers as u16, style: 0}, &frame_important));
+		assert!(stone.apply(&TileEdit::KillTile{fail: false}, &frame_important));
+	}
+	#[test]
+	fn apply_edits() {
+		let mut tile = Tile::default();
+		assert!(tile.apply(&TileEdit::PlaceTile{id: TileType::Stone as u16, style: 0}, &[]));
+		assert!(tile.apply(&TileEdit::PoundTile, &[]));
+		assert_eq!(tile.block.unwrap().slope, 1);
+		assert!(tile.apply(&TileEdit::from_raw(23, 3, 0), &[]));
+		assert_eq!(tile.block.unwrap().slope, 4); // Slopes are stored after half brick
+		assert!(tile.apply(&TileEdit::SlopeTile(0), &[]));
+		assert_eq!(tile.block.unwrap().slope, 0);
+
+		tile.block.as_mut().unwrap().color = 3;
+		assert!(tile.apply(&TileEdit::ReplaceTile{id: TileType::Dirt as u16, style: 0}, &[]));
+		assert_eq!(tile.block, Some(Block { color: 3, ..Block::new(TileType::Dirt) }));
+
+		assert!(tile.apply(&TileEdit::PlaceWire(3), &[]));
+		assert!(!tile.apply(&TileEdit::PlaceWire(3), &[]));
+		assert!(tile.apply(&TileEdit::PlaceActuator, &[]));
+		assert!(tile.apply(&TileEdit::Actuate, &[]));
+		assert!(tile.block.unwrap().is_inactive);
+		assert!(tile.apply(&TileEdit::KillTile{fail: true}, &[]));
+		assert!(tile.block.is_some()); // Failed hits only damage the tile
+		assert!(tile.apply(&TileEdit::KillTile{fail: false}, &[]));
+		assert!(tile.block.is_none());
+
+		assert!(tile.apply(&TileEdit::PlaceWall{id: 4}, &[]));
+		assert!(tile.apply(&TileEdit::ReplaceWall{id: 5}, &[]));
+		assert_eq!(tile.wall.unwrap().id as u16, 5);
+		assert!(tile.apply(&TileEdit::Other{action: 18, var1: 0, var2: 0}, &[])); // Relayed without changes
+	}
+}
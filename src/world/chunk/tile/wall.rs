@@ -3,15 +3,24 @@ use num::FromPrimitive;
 
 use serde::{Serialize, Deserialize};
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, Default)]
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
 pub struct Wall {
-	pub id: WallType,
+	pub id: u16, // Raw wall id, kept as is for ids without a WallType (from newer vanilla versions)
 	pub color: u8,
 }
+impl Wall {
+	pub fn new(id: WallType) -> Wall {
+		Wall { id: id as u16, ..Wall::default() }
+	}
+	/// None if terrarium doesn't know the wall id
+	pub fn wall_type(&self) -> Option<WallType> {
+		WallType::from_u16(self.id)
+	}
+}
 
 enum_from_primitive! {
 	#[repr(u16)]
-	#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+	#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 	pub enum WallType {
 		EmptyWall = 0,
 		Stone,
@@ -0,0 +1,129 @@
+use std::io::{self, Read, Write};
+use serde::{Deserialize, Serialize};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{TileCoord, chest::ItemStack};
+
+/// A tile that stores extra data (item frames, racks, pylons...)
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TileEntity {
+	pub id: i32, // Clients refer to tile entities by it
+	pub x: u32, // Top left tile of the object
+	pub y: u32,
+	pub kind: TileEntityKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum TileEntityKind {
+	TrainingDummy { npc: i16 }, // Index of the dummy's NPC, -1 if it isn't spawned
+	ItemFrame(ItemStack),
+	LogicSensor { check: u8, on: bool }, // What the sensor checks (day, night, player above...)
+	DisplayDoll { items: [Option<ItemStack>; 8], dyes: [Option<ItemStack>; 8] },
+	WeaponsRack(ItemStack),
+	HatRack { items: [Option<ItemStack>; 2], dyes: [Option<ItemStack>; 2] },
+	FoodPlatter(ItemStack),
+	TeleportationPylon,
+}
+
+impl TileEntity {
+	/// Read a tile entity in the vanilla format (used by world files & section packets)
+	pub fn read(reader: &mut impl Read) -> Result<TileEntity, io::Error> {
+		use TileEntityKind::*;
+		let kind = reader.read_u8()?;
+		let id = reader.read_i32::<LittleEndian>()?;
+		let x = reader.read_i16::<LittleEndian>()? as u32;
+		let y = reader.read_i16::<LittleEndian>()? as u32;
+		let kind = match kind {
+			0 => TrainingDummy { npc: reader.read_i16::<LittleEndian>()? },
+			1 => ItemFrame(read_item(reader)?),
+			2 => LogicSensor { check: reader.read_u8()?, on: reader.read_u8()? != 0 },
+			3 => {
+				let mut items = [None; 8];
+				let mut dyes = [None; 8];
+				read_slots(reader, &mut items, &mut dyes)?;
+				DisplayDoll { items, dyes }
+			},
+			4 => WeaponsRack(read_item(reader)?),
+			5 => {
+				let mut slots = [None; 4]; // Hat racks share one flag byte between items & dyes
+				let bits = reader.read_u8()?;
+				for (i, slot) in slots.iter_mut().enumerate() {
+					if bits & (1 << i) != 0 { *slot = Some(read_item(reader)?); }
+				}
+				HatRack { items: [slots[0], slots[1]], dyes: [slots[2], slots[3]] }
+			},
+			6 => FoodPlatter(read_item(reader)?),
+			7 => TeleportationPylon,
+			kind => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown tile entity type {} at {}, {}", kind, x, y))),
+		};
+		Ok(TileEntity { id, x, y, kind })
+	}
+	pub fn write(&self, writer: &mut impl Write) -> Result<(), io::Error> {
+		use TileEntityKind::*;
+		writer.write_u8(match self.kind {
+			TrainingDummy{..} => 0, ItemFrame(_) => 1, LogicSensor{..} => 2, DisplayDoll{..} => 3,
+			WeaponsRack(_) => 4, HatRack{..} => 5, FoodPlatter(_) => 6, TeleportationPylon => 7,
+		})?;
+		writer.write_i32::<LittleEndian>(self.id)?;
+		writer.write_i16::<LittleEndian>(self.x as i16)?;
+		writer.write_i16::<LittleEndian>(self.y as i16)?;
+		match &self.kind {
+			TrainingDummy { npc } => writer.write_i16::<LittleEndian>(*npc)?,
+			ItemFrame(item) | WeaponsRack(item) | FoodPlatter(item) => write_item(writer, item)?,
+			LogicSensor { check, on } => {
+				writer.write_u8(*check)?;
+				writer.write_u8(*on as u8)?;
+			},
+			DisplayDoll { items, dyes } => write_slots(writer, items, dyes)?,
+			HatRack { items, dyes } => {
+				let slots = [items[0], items[1], dyes[0], dyes[1]];
+				let bits = slots.iter().enumerate().fold(0u8, |bits, (i, slot)| bits | (slot.is_some() as u8) << i);
+				writer.write_u8(bits)?;
+				for item in slots.iter().flatten() { write_item(writer, item)?; }
+			},
+			TeleportationPylon => {},
+		}
+		Ok(())
+	}
+	pub fn coord(&self) -> TileCoord {
+		TileCoord { x: self.x as u16, y: self.y as u16 }
+	}
+}
+
+/// Room the town manager assigned to an NPC
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TownRoom {
+	pub npc: i32, // NPC type
+	pub home: TileCoord,
+}
+
+fn read_item(reader: &mut impl Read) -> Result<ItemStack, io::Error> {
+	let id = reader.read_i16::<LittleEndian>()? as u16;
+	let prefix = reader.read_u8()?;
+	let stack = reader.read_i16::<LittleEndian>()? as u16;
+	Ok(ItemStack { stack, id, prefix })
+}
+fn write_item(writer: &mut impl Write, item: &ItemStack) -> Result<(), io::Error> {
+	writer.write_i16::<LittleEndian>(item.id as i16)?;
+	writer.write_u8(item.prefix)?;
+	writer.write_i16::<LittleEndian>(item.stack as i16)?;
+	Ok(())
+}
+/// Flag bytes for which item & dye slots are set, then the set slots
+fn read_slots(reader: &mut impl Read, items: &mut [Option<ItemStack>], dyes: &mut [Option<ItemStack>]) -> Result<(), io::Error> {
+	let item_bits = reader.read_u8()?;
+	let dye_bits = reader.read_u8()?;
+	for (bits, slots) in [(item_bits, items), (dye_bits, dyes)].iter_mut() {
+		for (i, slot) in slots.iter_mut().enumerate() {
+			if *bits & (1 << i) != 0 { *slot = Some(read_item(reader)?); }
+		}
+	}
+	Ok(())
+}
+fn write_slots(writer: &mut impl Write, items: &[Option<ItemStack>], dyes: &[Option<ItemStack>]) -> Result<(), io::Error> {
+	let bits = |slots: &[Option<ItemStack>]| slots.iter().enumerate().fold(0u8, |bits, (i, slot)| bits | (slot.is_some() as u8) << i);
+	writer.write_u8(bits(items))?;
+	writer.write_u8(bits(dyes))?;
+	for item in items.iter().chain(dyes.iter()).flatten() { write_item(writer, item)?; }
+	Ok(())
+}
@@ -0,0 +1,301 @@
+//! Container header of .twld worlds & chunk files, and migration of older layouts to the current structs.
+//!
+//! Files start with `MAGIC`, the format version (u16) and flags (u16), followed by the bincode serialized body.
+//! Files written before the header existed have no magic and are read as version 0.
+//! When changing the layout of anything serialized in a world, bump `FORMAT_VERSION` and add a frozen copy of the old layout to `legacy` with a migration to the new one.
+
+#![allow(non_upper_case_globals)]
+
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Serialize, de::DeserializeOwned};
+
+pub const MAGIC: [u8; 4] = *b"TRRM";
+/// Layout version written by this build
+pub const FORMAT_VERSION: u16 = 1;
+
+bitflags! {
+	#[derive(Default)]
+	pub struct FormatFlags: u16 {
+		const Compressed = 0b00000001; // Body is deflate compressed
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum FormatError {
+	#[error("Error Reading / Writing File: {0}")]
+	Io(#[from] io::Error),
+	#[error("Error (De)serializing File Body: {0}")]
+	Bincode(#[from] bincode::Error),
+	#[error("File format version {found} is newer than this build supports ({supported}), update terrarium to open it")]
+	TooNew { found: u16, supported: u16 },
+	#[error("Unknown format flags: {0:#06x}")]
+	UnknownFlags(u16),
+	#[error("File has no format header and doesn't match the version 0 layout: {0}")]
+	UnknownLegacy(bincode::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+	pub version: u16,
+	pub flags: FormatFlags,
+}
+impl Header {
+	pub fn current() -> Header {
+		Header { version: FORMAT_VERSION, flags: FormatFlags::Compressed }
+	}
+	pub fn write(&self, writer: &mut impl Write) -> Result<(), io::Error> {
+		writer.write_all(&MAGIC)?;
+		writer.write_u16::<LittleEndian>(self.version)?;
+		writer.write_u16::<LittleEndian>(self.flags.bits())?;
+		Ok(())
+	}
+	/// Read header, returns version 0 (compressed, the only layout before headers) if file doesn't start with the magic.
+	/// The returned reader is positioned at the start of the (decompressed) body.
+	pub fn read<'a>(reader: &'a mut impl Read) -> Result<(Header, Box<dyn Read + 'a>), FormatError> {
+		let mut magic = [0u8; 4];
+		let read = read_up_to(reader, &mut magic)?;
+		let (header, body): (Header, Box<dyn Read + 'a>) = if read == magic.len() && magic == MAGIC {
+			let version = reader.read_u16::<LittleEndian>()?;
+			let flags = reader.read_u16::<LittleEndian>()?;
+			if version > FORMAT_VERSION { return Err(FormatError::TooNew { found: version, supported: FORMAT_VERSION }) }
+			let flags = FormatFlags::from_bits(flags).ok_or(FormatError::UnknownFlags(flags))?;
+			(Header { version, flags }, Box::new(reader))
+		} else {
+			// Put back bytes that weren't a header
+			let prefix = io::Cursor::new(magic[..read].to_vec());
+			(Header { version: 0, flags: FormatFlags::Compressed }, Box::new(prefix.chain(reader)))
+		};
+		if header.flags.contains(FormatFlags::Compressed) {
+			Ok((header, Box::new(DeflateDecoder::new(body))))
+		} else { Ok((header, body)) }
+	}
+}
+/// Like read_exact, but stops early at the end of the file
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, io::Error> {
+	let mut read = 0;
+	while read < buf.len() {
+		match reader.read(&mut buf[read..])? {
+			0 => break,
+			n => read += n,
+		}
+	}
+	Ok(read)
+}
+
+/// Write header & compressed body in the current format
+pub fn write(writer: &mut impl Write, body: &impl Serialize) -> Result<(), FormatError> {
+	let header = Header::current();
+	header.write(writer)?;
+	let mut writer = DeflateEncoder::new(writer, Compression::default());
+	bincode::serialize_into(&mut writer, body)?;
+	writer.finish()?;
+	Ok(())
+}
+pub fn deserialize<T: DeserializeOwned>(reader: &mut impl Read) -> Result<T, FormatError> {
+	Ok(bincode::deserialize_from(reader)?)
+}
+/// Deserialize a version 0 body, which may also be a file that isn't a world at all
+pub fn deserialize_legacy<T: DeserializeOwned>(reader: &mut impl Read) -> Result<T, FormatError> {
+	bincode::deserialize_from(reader).map_err(FormatError::UnknownLegacy)
+}
+
+/// Frozen layouts of older format versions
+pub mod legacy {
+	use serde::Deserialize;
+	#[cfg(test)]
+	use serde::Serialize;
+	use crate::world::{World, TileCoord, world_types::*};
+
+	/// World before version 1 (metadata only, tiles, NPCs, bestiary & creative powers weren't stored)
+	#[derive(Deserialize)]
+	#[cfg_attr(test, derive(Serialize))] // Tests write old layouts to check their migration
+	pub struct WorldV0 {
+		pub name: String,
+		pub id: i32,
+		pub uuid: u128,
+		pub gen_data: GenerationDataV0,
+		pub dimensions: Dimensions,
+		pub worldmode: WorldMode,
+		pub style: Style,
+		pub spawn_coord: TileCoord,
+		pub dungeon_coord: TileCoord,
+		pub progress: Progress,
+		pub time: Time,
+		pub weather: Weather,
+		pub events: EventsV0,
+		pub angler: Angler,
+		pub chunk_size: u16,
+	}
+	impl From<WorldV0> for World {
+		fn from(old: WorldV0) -> World {
+			World {
+				name: old.name, id: old.id, uuid: old.uuid,
+				gen_data: old.gen_data.into(), dimensions: old.dimensions, worldmode: old.worldmode, style: old.style,
+				spawn_coord: old.spawn_coord, dungeon_coord: old.dungeon_coord,
+				progress: old.progress, time: old.time, weather: old.weather, events: old.events.into(),
+				angler: old.angler,
+				chunk_size: old.chunk_size,
+				..World::default()
+			}
+		}
+	}
+
+	/// Generation data before version 1 (no seed text, generator version & special seeds)
+	#[derive(Deserialize)]
+	#[cfg_attr(test, derive(Serialize))]
+	pub struct GenerationDataV0 {
+		pub seed: u64,
+		pub corruption_type: CorruptionType,
+		pub surface_y: u32,
+		pub rock_layer_y: u32,
+		pub copper_tier: CopperTier,
+		pub iron_tier: IronTier,
+		pub silver_tier: SilverTier,
+		pub gold_tier: GoldTier,
+		pub cobalt_tier: CobaltTier,
+		pub mythril_tier: MythrilTier,
+		pub adamantite_tier: AdamantiteTier,
+	}
+	impl From<GenerationDataV0> for GenerationData {
+		fn from(old: GenerationDataV0) -> GenerationData {
+			GenerationData {
+				seed: old.seed, corruption_type: old.corruption_type, surface_y: old.surface_y, rock_layer_y: old.rock_layer_y,
+				copper_tier: old.copper_tier, iron_tier: old.iron_tier, silver_tier: old.silver_tier, gold_tier: old.gold_tier,
+				cobalt_tier: old.cobalt_tier, mythril_tier: old.mythril_tier, adamantite_tier: old.adamantite_tier,
+				..GenerationData::default()
+			}
+		}
+	}
+
+	/// Events before version 1 (no lantern night genuine & manual flags)
+	#[derive(Deserialize)]
+	#[cfg_attr(test, derive(Serialize))]
+	pub struct EventsV0 {
+		pub slime_rain_remaining_time: i32,
+		pub is_blood_moon_happening: bool,
+		pub is_meteor_queued: bool,
+		pub is_eclipse_happening: bool,
+		pub is_halloween_forced: bool,
+		pub is_christmas_forced: bool,
+		pub cultist_remaining_time: i32,
+		pub invasion_type: u8,
+		pub invasion_delay: i32,
+		pub invasion_start_size: i32,
+		pub invasion_size: i32,
+		pub invasion_x: u32,
+		pub lantern_night_cooldown: u8,
+		pub is_lantern_night_queued: bool,
+		pub is_solar_pillar_alive: bool,
+		pub is_vortex_pillar_alive: bool,
+		pub is_nebula_pillar_alive: bool,
+		pub is_stardust_pillar_alive: bool,
+		pub is_impending_doom_approaching: bool,
+		pub is_party_manual: bool,
+		pub is_party_genuine: bool,
+		pub party_cooldown: u8,
+		pub partying_npcs: Vec<u32>,
+	}
+	impl From<EventsV0> for Events {
+		fn from(old: EventsV0) -> Events {
+			Events {
+				slime_rain_remaining_time: old.slime_rain_remaining_time,
+				is_blood_moon_happening: old.is_blood_moon_happening,
+				is_meteor_queued: old.is_meteor_queued,
+				is_eclipse_happening: old.is_eclipse_happening,
+				is_halloween_forced: old.is_halloween_forced,
+				is_christmas_forced: old.is_christmas_forced,
+				cultist_remaining_time: old.cultist_remaining_time,
+				invasion_type: old.invasion_type,
+				invasion_delay: old.invasion_delay,
+				invasion_start_size: old.invasion_start_size,
+				invasion_size: old.invasion_size,
+				invasion_x: old.invasion_x,
+				lantern_night_cooldown: old.lantern_night_cooldown,
+				is_lantern_night_queued: old.is_lantern_night_queued,
+				is_solar_pillar_alive: old.is_solar_pillar_alive,
+				is_vortex_pillar_alive: old.is_vortex_pillar_alive,
+				is_nebula_pillar_alive: old.is_nebula_pillar_alive,
+				is_stardust_pillar_alive: old.is_stardust_pillar_alive,
+				is_impending_doom_approaching: old.is_impending_doom_approaching,
+				is_party_manual: old.is_party_manual,
+				is_party_genuine: old.is_party_genuine,
+				party_cooldown: old.party_cooldown,
+				partying_npcs: old.partying_npcs,
+				..Events::default()
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::world::{World, Chunk, TileCoord, world_types::*};
+
+	/// Body of a file written before headers existed
+	fn write_headerless(body: &impl Serialize) -> Vec<u8> {
+		let mut data = Vec::new();
+		let mut writer = DeflateEncoder::new(&mut data, Compression::default());
+		bincode::serialize_into(&mut writer, body).unwrap();
+		writer.finish().unwrap();
+		data
+	}
+	fn world_v0() -> legacy::WorldV0 {
+		legacy::WorldV0 {
+			name: "Legacy".into(), id: 7, uuid: 99,
+			gen_data: legacy::GenerationDataV0 {
+				seed: 42, corruption_type: CorruptionType::Crimson, surface_y: 300, rock_layer_y: 500,
+				copper_tier: CopperTier::TinOre, iron_tier: IronTier::LeadOre, silver_tier: SilverTier::TungstenOre, gold_tier: GoldTier::PlatinumOre,
+				cobalt_tier: CobaltTier::PalladiumOre, mythril_tier: MythrilTier::OrichalcumOre, adamantite_tier: AdamantiteTier::TitaniumOre,
+			},
+			dimensions: Dimensions { tile_width: 40, ..Dimensions::default() }, worldmode: WorldMode::Expert, style: Style::default(),
+			spawn_coord: TileCoord { x: 3, y: 4 }, dungeon_coord: TileCoord::default(),
+			progress: Progress::default(), time: Time { time: 1234, ..Time::default() }, weather: Weather::default(),
+			events: legacy::EventsV0 {
+				slime_rain_remaining_time: -1, is_blood_moon_happening: true, is_meteor_queued: false, is_eclipse_happening: false,
+				is_halloween_forced: false, is_christmas_forced: true, cultist_remaining_time: 10,
+				invasion_type: 2, invasion_delay: 0, invasion_start_size: 80, invasion_size: 60, invasion_x: 1000,
+				lantern_night_cooldown: 0, is_lantern_night_queued: false,
+				is_solar_pillar_alive: false, is_vortex_pillar_alive: true, is_nebula_pillar_alive: false, is_stardust_pillar_alive: false,
+				is_impending_doom_approaching: false, is_party_manual: false, is_party_genuine: true, party_cooldown: 3, partying_npcs: vec![17, 22],
+			},
+			angler: Angler::default(), chunk_size: 8,
+		}
+	}
+
+	#[test]
+	fn world_v0_migrates() {
+		let world = World::read(&mut &write_headerless(&world_v0())[..]).unwrap();
+		assert_eq!((world.name.as_str(), world.id, world.uuid, world.chunk_size), ("Legacy", 7, 99, 8));
+		assert_eq!((world.gen_data.seed, world.gen_data.surface_y, world.gen_data.rock_layer_y), (42, 300, 500));
+		assert!(matches!(world.gen_data.corruption_type, CorruptionType::Crimson));
+		assert!(matches!(world.gen_data.gold_tier, GoldTier::PlatinumOre));
+		assert!(matches!(world.gen_data.adamantite_tier, AdamantiteTier::TitaniumOre));
+		assert!(world.gen_data.seed_text.is_empty());
+		assert!(matches!(world.worldmode, WorldMode::Expert));
+		assert_eq!((world.dimensions.tile_width, world.spawn_coord, world.time.time), (40, TileCoord { x: 3, y: 4 }, 1234));
+		assert!(world.events.is_blood_moon_happening && world.events.is_party_genuine && world.events.is_vortex_pillar_alive);
+		assert_eq!((world.events.invasion_x, &world.events.partying_npcs), (1000, &vec![17, 22]));
+		assert!(!world.events.is_lantern_night_manual);
+		assert!(world.chunks.is_empty() && world.npcs.is_empty());
+	}
+	#[test]
+	fn current_world_round_trips() {
+		let mut data = Vec::new();
+		World { name: "Current".into(), chunk_size: 8, ..World::default() }.write(&mut data).unwrap();
+		assert_eq!(Header::read(&mut &data[..]).unwrap().0, Header::current());
+		let world = World::read(&mut &data[..]).unwrap();
+		assert_eq!((world.name.as_str(), world.chunk_size), ("Current", 8));
+	}
+	#[test]
+	fn newer_versions_are_rejected() {
+		let mut data = Vec::new();
+		Header { version: FORMAT_VERSION + 1, flags: FormatFlags::Compressed }.write(&mut data).unwrap();
+		data.extend(write_headerless(&world_v0()));
+		assert!(matches!(Header::read(&mut &data[..]), Err(FormatError::TooNew { .. })));
+		assert!(World::read(&mut &data[..]).is_err());
+		assert!(Chunk::read(&mut &data[..]).is_err());
+	}
+}
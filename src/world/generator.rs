@@ -1,7 +1,7 @@
 
 use rand::Rng;
 use crate::world::{
-	world_types::{CorruptionType},
+	world_types::{CorruptionType, SpecialSeeds},
 	GenerationData,
 	chunk::{Chunk, ChunkCoord, TileCoord},
 };
@@ -27,6 +27,9 @@ impl<R: Rng> WorldGenerator for NormalGen<R> {
 		let rng = &mut self.rng;
 		GenerationData {
 			seed: rng.gen(),
+			seed_text: String::new(),
+			generator_version: 0,
+			special_seeds: SpecialSeeds::default(),
 			corruption_type: self.corruption_type,
 			surface_y: rng.gen_range(100, 200),
 			rock_layer_y: rng.gen_range(3000, 3100),
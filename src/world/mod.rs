@@ -1,16 +1,19 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
-use log::{trace, error, warn, debug};
+use log::{trace, error, warn, debug, info};
 
 use std::{
 	io::{Read, Write, BufReader, BufWriter},
+	path::Path,
 	error::Error,
-	sync::Arc,
+	sync::{Arc, atomic::{AtomicU64, Ordering}},
 	convert::TryInto,
+	time::{Duration, Instant},
 	collections::HashMap
 };
 use rand::Rng;
+use rayon::prelude::*;
 
 use serde::{Deserialize, Serialize};
 use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
@@ -22,21 +25,36 @@ use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 
 use crate::server::*;
 
+pub mod autosave;
+pub mod backup;
+pub mod format;
 pub mod chunk;
 pub mod generator;
+pub mod npc;
+pub mod storage;
 pub mod vanilla;
 pub mod world_types;
 
-pub use chunk::{Chunk, ChunkAction, ChunkThread, LoadedChunk, ChunkActionSender, ChunkCoord, TileCoord};
+pub use chunk::{Chunk, ChunkAction, ChunkThread, ChunkContext, ChunkManager, LoadedChunk, ChunkActionSender, ChunkCoord, TileCoord, TileEdit, DEFAULT_CHUNK_SIZE};
+pub use autosave::Autosave;
+pub use storage::{WorldStorage, StorageKind};
 pub use generator::WorldGenerator;
+pub use npc::{Npc, Resident};
 pub use world_types::*;
 
 use crate::server::{ClientActionSender, ServerActionSender};
 
+/// How long chunks stay loaded after no player is near them
+const CHUNK_UNLOAD_DELAY: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 pub enum WorldAction {
-	SpawnClient(ClientActionSender, Option<TileCoord>), // Send back chunk thread
+	SpawnClient(ClientActionSender), // Send back chunk thread
 	RequestWorldInfo(ClientActionSender), // Sends back cached world info
+	RequestSections(usize, ClientActionSender, Option<TileCoord>), // Send area around spawn (or tile) to client with id
+	PlayerMoved(usize, TileCoord), // Client with id moved, send chunks that came into view
+	RemovePlayer(usize), // Client with id left, its chunks can be unloaded
+	Shutdown(), // Close chunk threads, save modified chunks & exit the world thread
 }
 pub type WorldActionSender = mpsc::Sender<WorldAction>;
 
@@ -46,6 +64,7 @@ pub struct World {
 	
 	pub id: i32, // Used for client side rendering
 	pub uuid: u128, // Used for client side maps
+	pub file: FileInfo,
 	
 	pub gen_data: GenerationData,
 	pub dimensions: Dimensions,
@@ -62,7 +81,13 @@ pub struct World {
 	
 	pub angler: Angler,
 	
+	pub npcs: Vec<Npc>, // Town NPCs & persistent mobs
+	pub bestiary: Bestiary,
+	pub creative_powers: CreativePowers,
+	
 	pub chunk_size: u16,
+	pub tile_frame_important: Vec<bool>, // Which block ids have frame data
+	pub chunks: HashMap<ChunkCoord, Chunk>, // Chunks stored in the world file (moved to region storage when the world is hosted)
 }
 
 impl World {
@@ -79,27 +104,36 @@ impl World {
 			let mut hasher = DefaultHasher::new();
 			seed.hash(&mut hasher);
 			world.gen_data.seed = hasher.finish();
+			world.gen_data.seed_text = seed.into();
 		}
 		world
 	}
 	pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
-		trace!("Uncompressing World Data");
-		let mut reader = DeflateDecoder::new(reader);
+		let (header, mut reader) = format::Header::read(reader)?;
 		
-		trace!("Deserializing World Data");
-		Ok(bincode::deserialize_from(&mut reader)?)
+		trace!("Deserializing World Data (format version {})", header.version);
+		Ok(match header.version {
+			0 => format::deserialize_legacy::<format::legacy::WorldV0>(&mut reader)?.into(),
+			_ => format::deserialize(&mut reader)?,
+		})
 	}
 	pub fn write(&self, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
-		trace!("Compressing World Data");
-		let mut writer = DeflateEncoder::new(writer, Compression::default());
-		
 		trace!("Serializing World Data");
-		bincode::serialize_into(&mut writer, self)?;
-		writer.finish()?;
-
+		format::write(writer, self)?;
 		Ok(())
 	}
-	pub fn update_worldinfo_cache(&self, cache: ArcSwap<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+	/// Write world to path without leaving a half written file if the write fails, keeping up to `backups` previous versions
+	pub fn save(&self, path: impl AsRef<Path>, backups: usize) -> Result<(), Box<dyn Error>> {
+		let path = path.as_ref();
+		backup::backup(path, backups)?;
+		backup::write_atomic(path, |file| -> Result<(), Box<dyn Error>> {
+			let mut writer = BufWriter::new(file);
+			self.write(&mut writer)?;
+			writer.flush()?;
+			Ok(())
+		})
+	}
+	pub fn update_worldinfo_cache(&self, cache: &ArcSwap<Vec<u8>>) -> Result<(), Box<dyn Error>> {
 		let mut writer = Vec::with_capacity(22 + 4 + self.name.len() + 170);
 		
 		writer.write_i32::<LittleEndian>(self.time.time as i32)?; // Time
@@ -122,7 +156,7 @@ impl World {
 		writer.write_varstring(&self.name)?;
 		writer.write_u8(self.worldmode as u8)?;
 		writer.write_u128::<LittleEndian>(self.uuid)?;
-		writer.write_u64::<LittleEndian>(1)?; //idk what this is "self Generator Version" (seems to be 1 in packet analysis)
+		writer.write_u64::<LittleEndian>(self.gen_data.generator_version)?;
 		writer.write_u8(self.style.moon_style)?; // ? Moon Type (set to 225 in packet analysis)
 		
 		writer.write_u8(self.style.forest_bg[0])?;
@@ -157,7 +191,14 @@ impl World {
 		
 		writer.write_f32::<LittleEndian>(self.weather.rain_amount)?;
 		
-		writer.write(&[0u8; 7][..])?; // Skipping event info
+		writer.write(&[0u8; 6][..])?; // Skipping event info
+		let seeds = &self.gen_data.special_seeds;
+		let mut flags = 0u8; // Town pets, drunk world, bosses & for the worthy
+		for (i, &set) in [self.progress.purchased_cat, self.progress.purchased_dog, self.progress.purchased_bunny, false, seeds.drunk,
+			self.progress.defeated_empress_of_light, self.progress.defeated_queen_slime, seeds.for_the_worthy].iter().enumerate() {
+			flags |= (set as u8) << i;
+		}
+		writer.write_u8(flags)?;
 		
 		writer.write_i16::<LittleEndian>(self.gen_data.copper_tier as i16)?;
 		writer.write_i16::<LittleEndian>(self.gen_data.iron_tier as i16)?;
@@ -174,48 +215,115 @@ impl World {
 		cache.store(Arc::new(writer));
 		Ok(())
 	}
-	pub async fn handle(&mut self, mut action_receiver: mpsc::Receiver<WorldAction>) -> Result<(), Box<dyn Error>> {
-		let mut chunks: HashMap<ChunkCoord, Option<LoadedChunk>> = HashMap::new();
-		
-		if self.chunk_size == 0 { self.chunk_size = 16; }
-		let spawn_chunk_coord = ChunkCoord::from_tilecoord(&self.spawn_coord, self.chunk_size);
-		chunks.insert(spawn_chunk_coord, Some(LoadedChunk::from_chunk(
-			Chunk::test_chunk(self.chunk_size)
-		)));
+	/// Move chunks stored in the world file (e.g. from a converted or older world) to storage, returns how many were moved.
+	/// Chunks are compressed on all cores, `progress` is called with how many are compressed & the total (possibly from several threads).
+	pub fn move_chunks_to(&mut self, storage: &dyn WorldStorage, progress: impl Fn(u64, u64) + Sync) -> Result<usize, Box<dyn Error>> {
+		let chunks: Vec<(ChunkCoord, Chunk)> = self.chunks.drain().collect();
+		let (done, total) = (AtomicU64::new(0), chunks.len() as u64);
+		let compressed = chunks.par_iter().map(|(coord, chunk)| {
+			let mut data = Vec::new();
+			chunk.write(&mut data).map_err(|err| err.to_string())?;
+			progress(done.fetch_add(1, Ordering::Relaxed) + 1, total);
+			Ok((*coord, data))
+		}).collect::<Result<Vec<_>, String>>()?;
+		
+		storage.save_chunks_data(&compressed)?;
+		Ok(compressed.len())
+	}
+	/// Snapshot metadata & modified chunks, then write them in the background (the task returns the chunks that weren't written)
+	async fn autosave(&self, chunks: &mut ChunkManager, autosave: &Autosave, storage: &Arc<dyn WorldStorage>) -> Result<tokio::task::JoinHandle<Vec<ChunkCoord>>, Box<dyn Error>> {
+		let start = Instant::now();
+		let mut metadata = Vec::new();
+		self.write(&mut metadata)?; // Chunks are held by the chunk manager, so this is just the metadata
+		let saved = chunks.collect_dirty().await;
+		
+		let (autosave, storage) = (autosave.clone(), storage.clone());
+		Ok(tokio::task::spawn_blocking(move || {
+			match autosave.write(&metadata, &saved, &*storage) {
+				Ok(_) => {
+					info!("Autosaved world with {} modified chunks in {:?}", saved.len(), start.elapsed());
+					Vec::new()
+				},
+				Err(err) => {
+					error!("Autosave failed, {} modified chunks will be saved again: {:?}", saved.len(), err);
+					saved.into_iter().map(|(coord, _)| coord).collect()
+				},
+			}
+		}))
+	}
+	pub async fn handle(&mut self, mut action_receiver: mpsc::Receiver<WorldAction>, storage: Arc<dyn WorldStorage>, autosave: Option<Autosave>) -> Result<(), Box<dyn Error>> {
+		if self.chunk_size == 0 { self.chunk_size = DEFAULT_CHUNK_SIZE; }
+		if !self.chunks.is_empty() {
+			warn!("Moving {} chunks from world to storage", self.move_chunks_to(&*storage, |_, _| {})?);
+		}
 		let world_info: ArcSwap<Vec<u8>> = ArcSwap::new(Arc::new(Vec::new())); // Initialize World ArcSwap Cache
+		self.update_worldinfo_cache(&world_info)?;
+		let chunk_context = Arc::new(ChunkContext {
+			world_size: TileCoord { x: self.dimensions.tile_width as u16, y: self.dimensions.tile_height as u16 },
+			tile_frame_important: self.tile_frame_important.clone(),
+		});
+		let mut chunks = ChunkManager::new(storage.clone(), chunk_context, self.chunk_size, &self.spawn_coord)?;
+		
+		let mut autosave_timer = tokio::time::interval(autosave.as_ref().map_or(Duration::from_secs(3600), |autosave| autosave.interval));
+		autosave_timer.tick().await; // First tick completes immediately
+		let mut autosave_task: Option<tokio::task::JoinHandle<Vec<ChunkCoord>>> = None;
+		let mut unload_timer = tokio::time::interval(CHUNK_UNLOAD_DELAY / 2);
 		
 		loop {
-			if let Some(action) = action_receiver.recv().await {
+			let action = tokio::select! {
+				action = action_receiver.recv() => action,
+				_ = autosave_timer.tick(), if autosave.is_some() => {
+					if let Some(task) = autosave_task.take() { chunks.mark_dirty(&task.await?); } // Don't overlap writes
+					match self.autosave(&mut chunks, autosave.as_ref().unwrap(), &storage).await {
+						Ok(task) => autosave_task = Some(task),
+						Err(err) => error!("Failed to start autosave: {:?}", err),
+					}
+					continue;
+				},
+				failed = async { autosave_task.as_mut().unwrap().await }, if autosave_task.is_some() => {
+					autosave_task = None;
+					chunks.mark_dirty(&failed?);
+					continue;
+				},
+				_ = unload_timer.tick(), if autosave_task.is_none() => { // Chunks being autosaved aren't dirty until their write succeeds, so keep them loaded
+					if let Err(err) = chunks.unload_unobserved(CHUNK_UNLOAD_DELAY).await {
+						error!("Failed to unload chunks: {:?}", err);
+					}
+					continue;
+				},
+			};
+			if let Some(action) = action {
 				use WorldAction::*;
 				match action {
 					// From Clients
-					SpawnClient(mut sender, tile_coord) => {
-						// parse chunk coord for sent tile coord or self.spawn_coord if tile coord not specified
-						let spawn_coord = tile_coord.unwrap_or(self.spawn_coord);
-						let spawn_chunk_coord = ChunkCoord::from_tilecoord(&spawn_coord, self.chunk_size);
-						if let Some(spawn_chunk) = chunks.get_mut(&spawn_chunk_coord) {
-							if let Some(loaded_chunk) = spawn_chunk {
-								let result = loaded_chunk.get_chunk_handle().await;
-								match result {
-									Ok(handler) => {
-										let h_cp = handler.clone();
-										sender.send(ClientAction::UpdateChunkHandler(h_cp)).await?;
-									},
-									Err(err) => { error!("Failed to get/create thread sender for chunk at {:?} err: {:?}", spawn_chunk_coord, err); continue; },
-								}
-							} else {
-								// TODO: Load chunk if not loaded and send asyncronously
-								error!("Chunk is not loaded at {:?}", spawn_chunk_coord); 
-							}
-						} else { warn!("Client attempted to load chunk outside of world"); }
+					SpawnClient(mut sender) => {
+						let handle = match chunks.handle().await {
+							Ok(handle) => handle,
+							Err(err) => { error!("Failed to get/create chunk thread: {:?}", err); continue; },
+						};
+						if sender.send(ClientAction::UpdateChunkHandler(handle)).await.is_err() { warn!("Client disconnected before it was spawned"); }
 					}
 					RequestWorldInfo(mut sender) => {
-						sender.send(ClientAction::SendPacket(packet::Packet::WorldInfo(world_info.clone()))).await?;
+						if sender.send(ClientAction::SendPacket(packet::Packet::WorldInfo(world_info.clone()))).await.is_err() { warn!("Client disconnected before receiving world info"); }
+					},
+					RequestSections(id, sender, tile_coord) => {
+						if let Err(err) = chunks.request_sections(id, sender, tile_coord).await {
+							error!("Failed to send sections to client {}: {:?}", id, err);
+						}
 					},
-					// From Chunks
-					
+					PlayerMoved(id, position) => {
+						if let Err(err) = chunks.player_moved(id, position).await {
+							error!("Failed to load chunks around client {}: {:?}", id, err);
+						}
+					},
+					RemovePlayer(id) => chunks.remove_player(id),
+					Shutdown() => break,
 				}
-			} else { return Ok(()) }
+			} else { break } // All senders dropped, save the same way as a shutdown
 		}
+		if let Some(task) = autosave_task.take() { chunks.mark_dirty(&task.await?); } // Don't write chunks from two places at once
+		if let Err(err) = storage.backup() { warn!("Failed to back up world, saving anyway: {:?}", err); } // Last save before the shutdown save overwrites it
+		chunks.shutdown().await?;
+		Ok(())
 	}
 }
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use super::TileCoord;
+
+/// An NPC saved with the world (town NPCs and mobs that persist, like the lunar pillars)
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Npc {
+	pub id: i32, // NPC type
+	pub x: f32, // Position in pixels
+	pub y: f32,
+	pub resident: Option<Resident>, // Set for town NPCs
+}
+
+/// Where & as who a town NPC lives
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Resident {
+	pub name: String, // Given name (e.g. "Andrew" for a guide)
+	pub home: TileCoord, // Tile of its house (kept when homeless)
+	pub homeless: bool,
+	pub variation: i32, // Alternate look of the NPC, 0 is the default
+}
+
+/// NPC type of the names worlds before version 190 store NPCs by
+pub fn id_from_legacy_name(name: &str) -> Option<i32> {
+	Some(match name {
+		"Merchant" => 17,
+		"Nurse" => 18,
+		"Arms Dealer" => 19,
+		"Dryad" => 20,
+		"Guide" => 22,
+		"Old Man" => 37,
+		"Demolitionist" => 38,
+		"Clothier" => 54,
+		"Goblin Tinkerer" => 107,
+		"Wizard" => 108,
+		"Mechanic" => 124,
+		"Santa Claus" => 142,
+		"Truffle" => 160,
+		"Steampunker" => 178,
+		"Dye Trader" => 207,
+		"Party Girl" => 208,
+		"Cyborg" => 209,
+		"Painter" => 227,
+		"Witch Doctor" => 228,
+		"Pirate" => 229,
+		"Stylist" => 353,
+		"Travelling Merchant" => 368,
+		"Angler" => 369,
+		"Tax Collector" => 441,
+		"Skeleton Merchant" => 453,
+		"Lunar Tower (Vortex)" | "Vortex Pillar" => 422,
+		"Lunar Tower (Stardust)" | "Stardust Pillar" => 493,
+		"Lunar Tower (Nebula)" | "Nebula Pillar" => 507,
+		"Lunar Tower (Solar)" | "Solar Pillar" => 517,
+		_ => return None,
+	})
+}
@@ -0,0 +1,62 @@
+use std::{
+	io::{self, Read, Write},
+	error::Error,
+	fs::File,
+	path::{Path, PathBuf},
+};
+
+use super::{WorldStorage, StorageError, RegionStorage};
+use crate::world::{ChunkCoord, backup};
+
+/// Metadata in the world file, chunks in region files next to it (`<world file>.regions/`)
+pub struct DirectoryStorage {
+	world_path: PathBuf,
+	backups: usize, // Old versions of the world file & region files kept by `backup`
+	regions: RegionStorage,
+}
+impl DirectoryStorage {
+	pub fn new(world_path: impl AsRef<Path>, backups: usize) -> DirectoryStorage {
+		let world_path = world_path.as_ref();
+		DirectoryStorage {
+			world_path: world_path.to_owned(),
+			backups,
+			regions: RegionStorage::for_world(world_path),
+		}
+	}
+}
+impl WorldStorage for DirectoryStorage {
+	fn load_metadata(&self) -> Result<Option<Vec<u8>>, StorageError> {
+		let mut file = match File::open(&self.world_path) {
+			Ok(file) => file,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(err) => return Err(err.into()),
+		};
+		let mut data = Vec::new();
+		file.read_to_end(&mut data)?;
+		Ok(Some(data))
+	}
+	fn save_metadata(&self, data: &[u8]) -> Result<(), StorageError> {
+		Ok(backup::write_atomic(&self.world_path, |file| file.write_all(data))?)
+	}
+	fn load_chunk_data(&self, coord: &ChunkCoord) -> Result<Option<Vec<u8>>, StorageError> {
+		Ok(self.regions.load_chunk_data(coord)?)
+	}
+	fn save_chunk_data(&self, coord: &ChunkCoord, data: &[u8]) -> Result<(), StorageError> {
+		Ok(self.regions.save_chunk(coord, data)?)
+	}
+	fn save_chunks_data(&self, chunks: &[(ChunkCoord, Vec<u8>)]) -> Result<(), StorageError> {
+		Ok(self.regions.save_chunks(chunks)?)
+	}
+	fn list_chunks(&self) -> Result<Vec<ChunkCoord>, StorageError> {
+		Ok(self.regions.list_chunks()?)
+	}
+	fn backup(&self) -> Result<(), StorageError> {
+		Ok(backup::backup(&self.world_path, self.backups)?)
+	}
+	fn backups(&self) -> Result<Vec<(u64, PathBuf)>, StorageError> {
+		Ok(backup::list(&self.world_path)?)
+	}
+	fn restore(&self, backup: &Path) -> Result<(), Box<dyn Error>> {
+		backup::restore(&self.world_path, backup, self.backups)
+	}
+}
@@ -0,0 +1,33 @@
+use std::{
+	sync::Mutex,
+	collections::HashMap,
+};
+
+use super::{WorldStorage, StorageError};
+use crate::world::ChunkCoord;
+
+/// Keeps everything in memory (for tests & throwaway servers)
+#[derive(Default)]
+pub struct MemoryStorage {
+	metadata: Mutex<Option<Vec<u8>>>,
+	chunks: Mutex<HashMap<ChunkCoord, Vec<u8>>>,
+}
+impl WorldStorage for MemoryStorage {
+	fn load_metadata(&self) -> Result<Option<Vec<u8>>, StorageError> {
+		Ok(self.metadata.lock().unwrap().clone())
+	}
+	fn save_metadata(&self, data: &[u8]) -> Result<(), StorageError> {
+		*self.metadata.lock().unwrap() = Some(data.to_vec());
+		Ok(())
+	}
+	fn load_chunk_data(&self, coord: &ChunkCoord) -> Result<Option<Vec<u8>>, StorageError> {
+		Ok(self.chunks.lock().unwrap().get(coord).cloned())
+	}
+	fn save_chunk_data(&self, coord: &ChunkCoord, data: &[u8]) -> Result<(), StorageError> {
+		self.chunks.lock().unwrap().insert(*coord, data.to_vec());
+		Ok(())
+	}
+	fn list_chunks(&self) -> Result<Vec<ChunkCoord>, StorageError> {
+		Ok(self.chunks.lock().unwrap().keys().copied().collect())
+	}
+}
@@ -0,0 +1,198 @@
+//! Where hosted worlds keep their metadata & chunks.
+//! Metadata is the `World::write` output of a world without chunks, chunks are `Chunk::write` output.
+
+use std::{
+	io,
+	error::Error,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+use serde::{Serialize, Deserialize};
+
+use super::{World, Chunk, ChunkCoord};
+
+pub mod region;
+pub mod directory;
+pub mod sqlite;
+pub mod memory;
+
+pub use region::RegionStorage;
+pub use directory::DirectoryStorage;
+pub use sqlite::SqliteStorage;
+pub use memory::MemoryStorage;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+	#[error("Error Reading / Writing World Storage: {0}")]
+	Io(#[from] io::Error),
+	#[error("SQLite Error: {0}")]
+	Sqlite(#[from] rusqlite::Error),
+}
+
+/// Backend selected in config.yml
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+	#[default]
+	Directory, // World file with region files next to it
+	Sqlite, // SQLite database next to the world file
+	Memory, // Nothing is written, changes are lost when the server stops
+}
+
+pub trait WorldStorage: Send + Sync {
+	/// Metadata of the stored world, None if nothing was saved yet
+	fn load_metadata(&self) -> Result<Option<Vec<u8>>, StorageError>;
+	fn save_metadata(&self, data: &[u8]) -> Result<(), StorageError>;
+	/// Compressed chunk, None if chunk was never saved
+	fn load_chunk_data(&self, coord: &ChunkCoord) -> Result<Option<Vec<u8>>, StorageError>;
+	fn save_chunk_data(&self, coord: &ChunkCoord, data: &[u8]) -> Result<(), StorageError>;
+	/// Save many compressed chunks at once (backends that can batch writes override this)
+	fn save_chunks_data(&self, chunks: &[(ChunkCoord, Vec<u8>)]) -> Result<(), StorageError> {
+		for (coord, data) in chunks {
+			self.save_chunk_data(coord, data)?;
+		}
+		Ok(())
+	}
+	fn list_chunks(&self) -> Result<Vec<ChunkCoord>, StorageError>;
+	/// Keep a copy of the stored metadata & chunks, removing the oldest copies past the configured count (if the backend supports it)
+	fn backup(&self) -> Result<(), StorageError> { Ok(()) }
+	/// Copies made by `backup`, newest first (unix time of backup, path)
+	fn backups(&self) -> Result<Vec<(u64, PathBuf)>, StorageError> { Ok(vec![]) }
+	/// Replace stored metadata & chunks with a backup, after backing up the current ones (not while the world is hosted)
+	fn restore(&self, _backup: &Path) -> Result<(), Box<dyn Error>> {
+		Err("World storage doesn't keep backups".into())
+	}
+
+	fn load_world(&self) -> Result<Option<World>, Box<dyn Error>> {
+		match self.load_metadata()? {
+			Some(data) => Ok(Some(World::read(&mut &data[..])?)),
+			None => Ok(None),
+		}
+	}
+	/// Save world metadata (chunks held by the world are not saved)
+	fn save_world(&self, world: &World) -> Result<(), Box<dyn Error>> {
+		let mut data = Vec::new();
+		world.write(&mut data)?;
+		Ok(self.save_metadata(&data)?)
+	}
+	fn load_chunk(&self, coord: &ChunkCoord) -> Result<Option<Chunk>, Box<dyn Error>> {
+		match self.load_chunk_data(coord)? {
+			Some(data) => Ok(Some(Chunk::read(&mut &data[..])?)),
+			None => Ok(None),
+		}
+	}
+	fn save_chunk(&self, coord: &ChunkCoord, chunk: &Chunk) -> Result<(), Box<dyn Error>> {
+		let mut data = Vec::new();
+		chunk.write(&mut data)?;
+		Ok(self.save_chunk_data(coord, &data)?)
+	}
+}
+
+/// Open storage of kind for the world file (backups is how many old versions of the world are kept)
+pub fn open(kind: StorageKind, world_path: impl AsRef<Path>, backups: usize) -> Result<Arc<dyn WorldStorage>, StorageError> {
+	let world_path = world_path.as_ref();
+	Ok(match kind {
+		StorageKind::Directory => Arc::new(DirectoryStorage::new(world_path, backups)),
+		StorageKind::Sqlite => {
+			let mut db_path = world_path.as_os_str().to_owned();
+			db_path.push(".sqlite");
+			Arc::new(SqliteStorage::open(db_path, backups)?)
+		},
+		StorageKind::Memory => Arc::new(MemoryStorage::default()),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::world::{TileCoord, Autosave};
+
+	fn round_trip(storage: &dyn WorldStorage) {
+		assert!(storage.load_metadata().unwrap().is_none());
+		assert!(storage.load_chunk(&ChunkCoord { x: 3, y: 40 }).unwrap().is_none());
+
+		let world = World { name: "Storage Test".into(), ..World::default() };
+		storage.save_world(&world).unwrap();
+		assert_eq!(storage.load_world().unwrap().unwrap().name, "Storage Test");
+
+		let coords = [ChunkCoord { x: 3, y: 40 }, ChunkCoord { x: 35, y: 0 }];
+		for coord in coords.iter() {
+			let chunk = Chunk::new(TileCoord { x: coord.x * 16, y: coord.y * 16 }, 16);
+			storage.save_chunk(coord, &chunk).unwrap();
+		}
+		// Overwriting a chunk replaces it
+		let chunk = Chunk::new(TileCoord { x: 48, y: 640 }, 8);
+		storage.save_chunk(&coords[0], &chunk).unwrap();
+		assert_eq!(storage.load_chunk(&coords[0]).unwrap().unwrap().chunk_size, 8);
+		assert_eq!(storage.load_chunk(&coords[1]).unwrap().unwrap().start_pos, TileCoord { x: 560, y: 0 });
+
+		// Chunks saved together are all stored
+		let batch: Vec<(ChunkCoord, Vec<u8>)> = [ChunkCoord { x: 4, y: 40 }, ChunkCoord { x: 70, y: 2 }].iter().map(|coord| {
+			let mut data = Vec::new();
+			Chunk::new(TileCoord { x: coord.x * 16, y: coord.y * 16 }, 16).write(&mut data).unwrap();
+			(*coord, data)
+		}).collect();
+		storage.save_chunks_data(&batch).unwrap();
+		assert_eq!(storage.load_chunk(&batch[1].0).unwrap().unwrap().start_pos, TileCoord { x: 1120, y: 32 });
+		
+		let mut listed = storage.list_chunks().unwrap();
+		listed.sort_by_key(|coord| (coord.x, coord.y));
+		let mut expected: Vec<ChunkCoord> = coords.iter().chain(batch.iter().map(|(coord, _)| coord)).copied().collect();
+		expected.sort_by_key(|coord| (coord.x, coord.y));
+		assert_eq!(listed, expected);
+	}
+	/// Autosaves back up the last save, restoring it brings back metadata & chunks as they were together
+	fn backup_and_restore(storage: &dyn WorldStorage) {
+		let coord = ChunkCoord { x: 1, y: 1 };
+		storage.save_world(&World { name: "Before".into(), ..World::default() }).unwrap();
+		storage.save_chunk(&coord, &Chunk::new(TileCoord { x: 16, y: 16 }, 16)).unwrap();
+
+		let (mut metadata, mut chunk) = (Vec::new(), Vec::new());
+		World { name: "After".into(), ..World::default() }.write(&mut metadata).unwrap();
+		Chunk::new(TileCoord { x: 16, y: 16 }, 8).write(&mut chunk).unwrap();
+		Autosave::new(60).unwrap().write(&metadata, &[(coord, chunk)], storage).unwrap();
+		assert_eq!(storage.load_world().unwrap().unwrap().name, "After");
+		let backups = storage.backups().unwrap();
+		assert_eq!(backups.len(), 1);
+		storage.restore(&backups[0].1).unwrap();
+
+		assert_eq!(storage.load_world().unwrap().unwrap().name, "Before");
+		assert_eq!(storage.load_chunk(&coord).unwrap().unwrap().chunk_size, 16);
+		assert_eq!(storage.backups().unwrap().len(), 2); // State before restoring is kept
+	}
+	fn temp_world_path(name: &str) -> std::path::PathBuf {
+		let dir = std::env::temp_dir().join(format!("terrarium-storage-test-{}-{}", name, std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir.join("world.twld")
+	}
+
+	#[test]
+	fn memory_round_trip() {
+		round_trip(&*open(StorageKind::Memory, "unused.twld", 0).unwrap());
+	}
+	#[test]
+	fn directory_round_trip() {
+		let path = temp_world_path("directory");
+		round_trip(&*open(StorageKind::Directory, &path, 2).unwrap());
+		let _ = std::fs::remove_dir_all(path.parent().unwrap());
+	}
+	#[test]
+	fn sqlite_round_trip() {
+		let path = temp_world_path("sqlite");
+		round_trip(&*open(StorageKind::Sqlite, &path, 0).unwrap());
+		let _ = std::fs::remove_dir_all(path.parent().unwrap());
+	}
+	#[test]
+	fn directory_backup_and_restore() {
+		let path = temp_world_path("directory-backup");
+		backup_and_restore(&*open(StorageKind::Directory, &path, 2).unwrap());
+		let _ = std::fs::remove_dir_all(path.parent().unwrap());
+	}
+	#[test]
+	fn sqlite_backup_and_restore() {
+		let path = temp_world_path("sqlite-backup");
+		backup_and_restore(&*open(StorageKind::Sqlite, &path, 2).unwrap());
+		let _ = std::fs::remove_dir_all(path.parent().unwrap());
+	}
+}
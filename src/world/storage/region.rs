@@ -0,0 +1,237 @@
+//! Region files store the chunks of a world outside of the world file, so chunks can be loaded and saved one at a time.
+//!
+//! Each region holds `REGION_SIZE` x `REGION_SIZE` chunks in `<world file>.regions/r.<x>.<y>.region`.
+//! A region file starts with `REGION_MAGIC` and an offset table with an (offset, length) entry for every chunk in the region,
+//! followed by chunk data written with `Chunk::write`. Saving a chunk appends its data and then points its table entry at it,
+//! so a crash mid-write leaves the previous copy of the chunk in place.
+//! Once old copies take up more space than the live chunks, the region is rewritten with only the live chunks.
+
+use log::debug;
+use std::{
+	io::{self, Read, Write, Seek, SeekFrom, BufWriter},
+	fs::{self, File, OpenOptions},
+	path::{Path, PathBuf},
+	sync::Mutex,
+	collections::HashMap,
+};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::world::{ChunkCoord, backup::write_atomic};
+
+/// Width & height of a region in chunks
+pub const REGION_SIZE: u16 = 32;
+const REGION_MAGIC: [u8; 4] = *b"TRRG";
+const ENTRY_SIZE: u64 = 8; // u32 offset & u32 length
+const TABLE_START: u64 = REGION_MAGIC.len() as u64;
+const ENTRY_COUNT: usize = REGION_SIZE as usize * REGION_SIZE as usize;
+const DATA_START: u64 = TABLE_START + ENTRY_COUNT as u64 * ENTRY_SIZE;
+/// Regions aren't compacted until old chunk copies take up at least this much space
+const COMPACT_MIN_DEAD: u64 = 64 * 1024;
+
+pub struct RegionStorage {
+	dir: PathBuf,
+	lock: Mutex<()>, // Region files are read & written by the world thread and autosaves
+}
+impl RegionStorage {
+	/// Region storage belonging to a world file
+	pub fn for_world(world_path: impl AsRef<Path>) -> RegionStorage {
+		RegionStorage::new(RegionStorage::dir_for_world(world_path))
+	}
+	/// Directory region files of a world file are kept in
+	pub fn dir_for_world(world_path: impl AsRef<Path>) -> PathBuf {
+		let mut dir = world_path.as_ref().as_os_str().to_owned();
+		dir.push(".regions");
+		PathBuf::from(dir)
+	}
+	pub fn new(dir: impl Into<PathBuf>) -> RegionStorage {
+		RegionStorage { dir: dir.into(), lock: Mutex::new(()) }
+	}
+	fn region_path(&self, coord: &ChunkCoord) -> PathBuf {
+		self.dir.join(format!("r.{}.{}.region", coord.x / REGION_SIZE, coord.y / REGION_SIZE))
+	}
+	fn entry_index(coord: &ChunkCoord) -> usize {
+		(coord.y % REGION_SIZE) as usize * REGION_SIZE as usize + (coord.x % REGION_SIZE) as usize
+	}
+	fn entry_offset(coord: &ChunkCoord) -> u64 {
+		TABLE_START + RegionStorage::entry_index(coord) as u64 * ENTRY_SIZE
+	}
+	/// Load compressed chunk, None if it was never saved
+	pub fn load_chunk_data(&self, coord: &ChunkCoord) -> Result<Option<Vec<u8>>, io::Error> {
+		let _lock = self.lock.lock().unwrap();
+		let mut file = match File::open(self.region_path(coord)) {
+			Ok(file) => file,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(err) => return Err(err),
+		};
+		file.seek(SeekFrom::Start(RegionStorage::entry_offset(coord)))?;
+		let offset = file.read_u32::<LittleEndian>()?;
+		let length = file.read_u32::<LittleEndian>()?;
+		if length == 0 { return Ok(None) }
+
+		file.seek(SeekFrom::Start(offset as u64))?;
+		let mut data = vec![0u8; length as usize];
+		file.read_exact(&mut data)?;
+		Ok(Some(data))
+	}
+	/// Save compressed chunk data (from `Chunk::write`)
+	pub fn save_chunk(&self, coord: &ChunkCoord, data: &[u8]) -> Result<(), io::Error> {
+		let _lock = self.lock.lock().unwrap();
+		self.append(&self.region_path(coord), &[(*coord, data)])?;
+		debug!("Saved chunk {:?} ({} bytes)", coord, data.len());
+		Ok(())
+	}
+	/// Save many chunks, syncing each region file once instead of once per chunk
+	pub fn save_chunks(&self, chunks: &[(ChunkCoord, Vec<u8>)]) -> Result<(), io::Error> {
+		let _lock = self.lock.lock().unwrap();
+		let mut regions: HashMap<PathBuf, Vec<(ChunkCoord, &[u8])>> = HashMap::new();
+		for (coord, data) in chunks {
+			regions.entry(self.region_path(coord)).or_default().push((*coord, data));
+		}
+		for (path, chunks) in regions {
+			self.append(&path, &chunks)?;
+			debug!("Saved {} chunks to {}", chunks.len(), path.display());
+		}
+		Ok(())
+	}
+	/// Append chunks to a region file, then point their table entries at them (compacting the region if it's mostly old copies)
+	fn append(&self, path: &Path, chunks: &[(ChunkCoord, &[u8])]) -> Result<(), io::Error> {
+		fs::create_dir_all(&self.dir)?;
+		let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?; // Keep existing chunks
+		if file.metadata()?.len() < DATA_START {
+			file.write_all(&REGION_MAGIC)?;
+			file.write_all(&vec![0u8; (DATA_START - TABLE_START) as usize])?;
+		}
+		let table = read_table(&mut file)?;
+		
+		// Space taken by chunks once these are saved, anything else in the file is an old copy
+		let mut live: Vec<u64> = table.iter().map(|(_, length)| *length as u64).collect();
+		for (coord, data) in chunks {
+			live[RegionStorage::entry_index(coord)] = data.len() as u64;
+		}
+		let live: u64 = live.iter().sum();
+		let mut offset = file.seek(SeekFrom::End(0))?;
+		let end = offset + chunks.iter().map(|(_, data)| data.len() as u64).sum::<u64>();
+		let dead = end - DATA_START - live;
+		if end > u32::MAX as u64 || (dead > live && dead > COMPACT_MIN_DEAD) {
+			return compact(path, &mut file, &table, chunks);
+		}
+		
+		let mut entries = Vec::with_capacity(chunks.len());
+		let mut writer = BufWriter::new(&mut file);
+		for (coord, data) in chunks {
+			writer.write_all(data)?;
+			entries.push((coord, entry(offset, data.len())?));
+			offset += data.len() as u64;
+		}
+		writer.flush()?;
+		drop(writer);
+		file.sync_data()?;
+
+		for (coord, entry) in entries {
+			file.seek(SeekFrom::Start(RegionStorage::entry_offset(coord)))?;
+			file.write_all(&entry)?; // Offset & length in one write, so they can't be torn apart
+		}
+		file.sync_data()?;
+		Ok(())
+	}
+	/// Coordinates of all saved chunks
+	pub fn list_chunks(&self) -> Result<Vec<ChunkCoord>, io::Error> {
+		let _lock = self.lock.lock().unwrap();
+		let mut coords = vec![];
+		if !self.dir.exists() { return Ok(coords) }
+		for entry in fs::read_dir(&self.dir)? {
+			let path = entry?.path();
+			let region = path.file_name().and_then(|name| name.to_str()).and_then(|name| {
+				let mut parts = name.strip_prefix("r.")?.strip_suffix(".region")?.split('.');
+				Some((parts.next()?.parse::<u16>().ok()?, parts.next()?.parse::<u16>().ok()?))
+			});
+			let (region_x, region_y) = match region { Some(region) => region, None => continue };
+
+			for (index, _) in read_table(&mut File::open(&path)?)?.iter().enumerate().filter(|(_, (_, length))| *length != 0) {
+				coords.push(ChunkCoord {
+					x: region_x * REGION_SIZE + (index % REGION_SIZE as usize) as u16,
+					y: region_y * REGION_SIZE + (index / REGION_SIZE as usize) as u16,
+				});
+			}
+		}
+		Ok(coords)
+	}
+}
+
+/// (offset, length) of every chunk in a region file
+fn read_table(file: &mut File) -> Result<Vec<(u32, u32)>, io::Error> {
+	let mut table = vec![0u8; ENTRY_COUNT * ENTRY_SIZE as usize];
+	file.seek(SeekFrom::Start(TABLE_START))?;
+	file.read_exact(&mut table)?;
+	Ok(table.chunks_exact(ENTRY_SIZE as usize).map(|entry| (LittleEndian::read_u32(&entry[..4]), LittleEndian::read_u32(&entry[4..]))).collect())
+}
+/// Table entry of chunk data at offset, fails if the region has grown past what the table can point to
+fn entry(offset: u64, length: usize) -> Result<[u8; ENTRY_SIZE as usize], io::Error> {
+	if offset + length as u64 > u32::MAX as u64 {
+		return Err(io::Error::other("Region file is larger than 4 GiB"))
+	}
+	let mut entry = [0u8; ENTRY_SIZE as usize];
+	LittleEndian::write_u32(&mut entry[..4], offset as u32);
+	LittleEndian::write_u32(&mut entry[4..], length as u32);
+	Ok(entry)
+}
+/// Rewrite region with only the newest copy of each chunk (and the chunks being saved) through a temporary file
+fn compact(path: &Path, old: &mut File, table: &[(u32, u32)], chunks: &[(ChunkCoord, &[u8])]) -> Result<(), io::Error> {
+	let mut saved: Vec<Option<&[u8]>> = vec![None; ENTRY_COUNT];
+	for (coord, data) in chunks {
+		saved[RegionStorage::entry_index(coord)] = Some(data);
+	}
+	let mut new_table = vec![0u8; ENTRY_COUNT * ENTRY_SIZE as usize];
+	write_atomic(path, |file| -> Result<(), io::Error> {
+		let mut writer = BufWriter::new(file);
+		writer.write_all(&REGION_MAGIC)?;
+		writer.write_all(&new_table)?;
+		let mut offset = DATA_START;
+		let mut buffer = Vec::new();
+		for (index, &(old_offset, old_length)) in table.iter().enumerate() {
+			let data = match saved[index] {
+				Some(data) => data,
+				None if old_length != 0 => {
+					buffer.resize(old_length as usize, 0);
+					old.seek(SeekFrom::Start(old_offset as u64))?;
+					old.read_exact(&mut buffer)?;
+					&buffer[..]
+				},
+				None => continue,
+			};
+			writer.write_all(data)?;
+			new_table[index * ENTRY_SIZE as usize..][..ENTRY_SIZE as usize].copy_from_slice(&entry(offset, data.len())?);
+			offset += data.len() as u64;
+		}
+		let file = writer.into_inner().map_err(|err| err.into_error())?;
+		file.seek(SeekFrom::Start(TABLE_START))?;
+		file.write_all(&new_table)
+	})?;
+	debug!("Compacted {}", path.display());
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn old_copies_are_compacted() {
+		let dir = std::env::temp_dir().join(format!("terrarium-region-test-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		let storage = RegionStorage::new(&dir);
+		let (a, b) = (ChunkCoord { x: 33, y: 2 }, ChunkCoord { x: 40, y: 31 });
+		storage.save_chunk(&b, &[7u8; 10_000]).unwrap();
+		for i in 0..20u8 {
+			storage.save_chunks(&[(a, vec![i; 100_000])]).unwrap();
+		}
+		let size = fs::metadata(storage.region_path(&a)).unwrap().len();
+		assert!(size < DATA_START + 3 * 110_000, "region grew to {} bytes", size);
+		assert_eq!(storage.load_chunk_data(&a).unwrap().unwrap(), vec![19u8; 100_000]);
+		assert_eq!(storage.load_chunk_data(&b).unwrap().unwrap(), vec![7u8; 10_000]);
+		let mut listed = storage.list_chunks().unwrap();
+		listed.sort_by_key(|coord| (coord.x, coord.y));
+		assert_eq!(listed, vec![a, b]);
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
@@ -0,0 +1,87 @@
+use log::info;
+use std::{
+	error::Error,
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+use rusqlite::{Connection, DatabaseName, OptionalExtension, params, backup::Progress};
+
+use super::{WorldStorage, StorageError};
+use crate::world::{ChunkCoord, backup};
+
+/// Metadata & chunks as blobs in an SQLite database
+pub struct SqliteStorage {
+	connection: Mutex<Connection>,
+	path: PathBuf,
+	backups: usize, // Old copies of the database kept by `backup`
+}
+impl SqliteStorage {
+	pub fn open(path: impl AsRef<Path>, backups: usize) -> Result<SqliteStorage, StorageError> {
+		let path = path.as_ref();
+		let connection = Connection::open(path)?;
+		connection.execute_batch("
+			PRAGMA journal_mode = WAL;
+			CREATE TABLE IF NOT EXISTS metadata (id INTEGER PRIMARY KEY CHECK (id = 0), data BLOB NOT NULL);
+			CREATE TABLE IF NOT EXISTS chunks (x INTEGER NOT NULL, y INTEGER NOT NULL, data BLOB NOT NULL, PRIMARY KEY (x, y));
+		")?;
+		Ok(SqliteStorage { connection: Mutex::new(connection), path: path.to_owned(), backups })
+	}
+	/// Copy database next to it with SQLite's backup API, which gets a consistent copy even with changes still in the WAL
+	fn copy_to_backups(&self) -> Result<(), StorageError> {
+		let connection = self.connection.lock().unwrap();
+		backup::backup_with(&self.path, |path| Ok(connection.backup(DatabaseName::Main, path, None)?))
+	}
+}
+impl WorldStorage for SqliteStorage {
+	fn load_metadata(&self) -> Result<Option<Vec<u8>>, StorageError> {
+		let connection = self.connection.lock().unwrap();
+		Ok(connection.query_row("SELECT data FROM metadata WHERE id = 0", params![], |row| row.get(0)).optional()?)
+	}
+	fn save_metadata(&self, data: &[u8]) -> Result<(), StorageError> {
+		let connection = self.connection.lock().unwrap();
+		connection.execute("INSERT OR REPLACE INTO metadata (id, data) VALUES (0, ?1)", params![data])?;
+		Ok(())
+	}
+	fn load_chunk_data(&self, coord: &ChunkCoord) -> Result<Option<Vec<u8>>, StorageError> {
+		let connection = self.connection.lock().unwrap();
+		Ok(connection.query_row("SELECT data FROM chunks WHERE x = ?1 AND y = ?2", params![coord.x, coord.y], |row| row.get(0)).optional()?)
+	}
+	fn save_chunk_data(&self, coord: &ChunkCoord, data: &[u8]) -> Result<(), StorageError> {
+		let connection = self.connection.lock().unwrap();
+		connection.execute("INSERT OR REPLACE INTO chunks (x, y, data) VALUES (?1, ?2, ?3)", params![coord.x, coord.y, data])?;
+		Ok(())
+	}
+	fn save_chunks_data(&self, chunks: &[(ChunkCoord, Vec<u8>)]) -> Result<(), StorageError> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction()?; // One commit for all chunks
+		{
+			let mut statement = transaction.prepare("INSERT OR REPLACE INTO chunks (x, y, data) VALUES (?1, ?2, ?3)")?;
+			for (coord, data) in chunks {
+				statement.execute(params![coord.x, coord.y, data])?;
+			}
+		}
+		transaction.commit()?;
+		Ok(())
+	}
+	fn list_chunks(&self) -> Result<Vec<ChunkCoord>, StorageError> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare("SELECT x, y FROM chunks")?;
+		let coords = statement.query_map(params![], |row| Ok(ChunkCoord { x: row.get(0)?, y: row.get(1)? }))?;
+		Ok(coords.collect::<Result<Vec<_>, _>>()?)
+	}
+	fn backup(&self) -> Result<(), StorageError> {
+		if self.backups == 0 { return Ok(()) }
+		self.copy_to_backups()?;
+		Ok(backup::prune(&self.path, self.backups)?)
+	}
+	fn backups(&self) -> Result<Vec<(u64, PathBuf)>, StorageError> {
+		Ok(backup::list(&self.path)?)
+	}
+	fn restore(&self, backup: &Path) -> Result<(), Box<dyn Error>> {
+		self.copy_to_backups()?;
+		self.connection.lock().unwrap().restore(DatabaseName::Main, backup, None::<fn(Progress)>)?;
+		backup::prune(&self.path, self.backups.max(1))?;
+		info!("Restored {:?} from {:?}", self.path, backup);
+		Ok(())
+	}
+}
@@ -1,75 +1,335 @@
 use log::{debug, warn};
 use std::{
-	io::{self, Read},
+	io::{self, Read, Write, Seek, SeekFrom},
 	hash::{Hash, Hasher},
+	fmt,
 	convert::{TryFrom, TryInto},
 	error::Error,
-	collections::hash_map::DefaultHasher,
+	collections::{HashMap, hash_map::DefaultHasher},
+	sync::atomic::{AtomicU64, Ordering},
 };
 use rand::random;
+use rayon::prelude::*;
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use variant_encoding::VarStringReader;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use variant_encoding::{VarStringReader, VarStringWriter};
 use crate::enum_primitive::FromPrimitive;
+use crate::data;
 
 use crate::world::{
 	chunk::{
-		Sign, Tile, Chest, chest::ItemStack,
-		tile::{Wall, WallType, Liquid, Block, TileCoord},
+		Chunk, ChunkCoord, DEFAULT_CHUNK_SIZE, write_packed_tile,
+		Sign, Tile, Chest, chest::ItemStack, TileEntity, TownRoom,
+		tile::{Wall, WallType, Liquid, Block, TileType, TileCoord},
 	},
-	World, WorldMode, CorruptionType,
+	World, WorldMode, CorruptionType, Npc, Resident, npc, Bestiary, CreativePowers, FileInfo,
 	//test::IronTier,
 	world_types::{CopperTier, IronTier, SilverTier, GoldTier, CobaltTier, MythrilTier, AdamantiteTier},
 };
 
-/// Read a vanilla format world into a [World](../world/struct.World.html).
-pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
-	let mut world = World::default();
+/// Vanilla world version written by `write` (1.4.0.5)
+pub const VERSION: i32 = 230;
+/// Oldest world version `read` accepts (1.3.0.1)
+pub const MIN_VERSION: i32 = 146;
+/// Newest world version `read` knows the layout of (1.4.4.9)
+pub const MAX_VERSION: i32 = 279;
+/// Tile ids known to version 230 (length of the tile frame importance table)
+pub const TILE_COUNT: usize = 623;
+/// Sections after the file header in version 230 files (world header, tiles, chests, signs, npcs, tile entities, pressure plates, town manager, bestiary, creative powers & footer)
+const SECTION_COUNT: usize = 11;
+/// Items in every chest
+const CHEST_SIZE: usize = 40;
+
+// Ids of the creative powers saved with the world (every per-world power up to version 279, the others are per player)
+const POWER_FREEZE_TIME: u16 = 0;
+const POWER_TIME_SPEED: u16 = 8;
+const POWER_FREEZE_RAIN: u16 = 9;
+const POWER_FREEZE_WIND: u16 = 10;
+const POWER_DIFFICULTY: u16 = 12;
+const POWER_FREEZE_BIOME_SPREAD: u16 = 13;
+
+#[derive(Error, Debug)]
+pub enum VanillaError {
+	#[error("Error Reading Vanilla World: {0}")]
+	Io(#[from] io::Error),
+	#[error("Unsupported Vanilla World Version: {0} (supported are {} to {})", MIN_VERSION, MAX_VERSION)]
+	UnsupportedVersion(i32),
+	#[error("Not a vanilla world file")]
+	NotAWorld,
+	#[error("File header has no offset for the {0} section")]
+	MissingSection(Section),
+	#[error("Failed to read {section} section at byte {offset}: {source}")]
+	Parse { section: Section, offset: u64, source: SectionError },
+	#[error("{section} section ended at byte {found}, but the next section starts at byte {expected}")]
+	SectionMismatch { section: Section, expected: u64, found: u64 },
+	#[error("Footer names world {name:?} ({id}), but the header names {expected_name:?} ({expected_id})")]
+	FooterMismatch { name: String, id: i32, expected_name: String, expected_id: i32 },
+}
+/// Errors while parsing a section (wrapped in `VanillaError::Parse` with where it happened)
+pub type SectionError = Box<dyn Error + Send + Sync>;
+
+/// Parts of a vanilla world file, in the order they're stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+	FileHeader, // Version, offsets of the other sections & tile frame importance
+	WorldHeader,
+	Tiles,
+	Chests,
+	Signs,
+	Npcs,
+	TileEntities,
+	PressurePlates,
+	TownManager,
+	Bestiary,
+	CreativePowers,
+	Footer,
+}
+impl Section {
+	/// Sections listed in the offset table of files with version (in order)
+	fn in_version(version: i32) -> Vec<Section> {
+		use Section::*;
+		let mut sections = vec![WorldHeader, Tiles, Chests, Signs, Npcs, TileEntities];
+		if version >= 170 { sections.push(PressurePlates); }
+		if version >= 189 { sections.push(TownManager); }
+		if version >= 210 { sections.push(Bestiary); }
+		if version >= 220 { sections.push(CreativePowers); }
+		sections.push(Footer);
+		sections
+	}
+}
+impl fmt::Display for Section {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		use Section::*;
+		f.write_str(match self {
+			FileHeader => "file header",
+			WorldHeader => "world header",
+			Tiles => "tiles",
+			Chests => "chests",
+			Signs => "signs",
+			Npcs => "NPCs",
+			TileEntities => "tile entities",
+			PressurePlates => "pressure plates",
+			TownManager => "town manager",
+			Bestiary => "bestiary",
+			CreativePowers => "creative powers",
+			Footer => "footer",
+		})
+	}
+}
+
+/// What `read_with_progress` is working on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+	Tiles, // Columns of tiles decoded
+	Chests,
+	Signs,
+	Chunks, // Columns of chunks built
+}
+impl fmt::Display for Stage {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Stage::Tiles => "Tiles",
+			Stage::Chests => "Chests",
+			Stage::Signs => "Signs",
+			Stage::Chunks => "Chunks",
+		})
+	}
+}
+
+/// Where each section starts (from the file header)
+struct SectionTable {
+	sections: Vec<(Section, u64)>,
+}
+impl SectionTable {
+	fn new(version: i32, offsets: Vec<u64>) -> Result<SectionTable, VanillaError> {
+		let expected = Section::in_version(version);
+		if let Some(&missing) = expected.get(offsets.len()) { return Err(VanillaError::MissingSection(missing)) }
+		Ok(SectionTable { sections: expected.into_iter().zip(offsets).collect() })
+	}
+	fn offset(&self, section: Section) -> Result<u64, VanillaError> {
+		self.sections.iter().find(|(s, _)| *s == section).map(|(_, offset)| *offset).ok_or(VanillaError::MissingSection(section))
+	}
+	/// Where section ends (the start of the next section), None for the last section
+	fn end(&self, section: Section) -> Option<u64> {
+		self.sections.iter().skip_while(|(s, _)| *s != section).nth(1).map(|(_, offset)| *offset)
+	}
+	/// Seek to the start of section and parse it, checking it ends where the next section starts
+	fn read<R: Read + Seek, T>(&self, reader: &mut R, section: Section, parse: impl FnOnce(&mut R) -> Result<T, SectionError>) -> Result<T, VanillaError> {
+		let start = self.offset(section)?;
+		reader.seek(SeekFrom::Start(start))?;
+		let value = match parse(reader) {
+			Ok(value) => value,
+			Err(source) => return Err(VanillaError::Parse { section, offset: reader.stream_position().unwrap_or(start), source }),
+		};
+		
+		if let Some(expected) = self.end(section) {
+			let found = reader.stream_position()?;
+			if found != expected { return Err(VanillaError::SectionMismatch { section, expected, found }) }
+		}
+		Ok(value)
+	}
+}
 
+/// Read a vanilla format world (1.3 or later) into a [World](../world/struct.World.html).
+/// Fields are read depending on the file version the same way the vanilla loader does,
+/// each section is read from its offset in the file header and has to end where the next one starts.
+pub fn read(reader: &mut (impl Read + Seek)) -> Result<World, VanillaError> {
+	read_with_progress(reader, |_, _, _| {})
+}
+/// Like `read`, calling `progress` with the stage, how much of it is done & its total while reading tiles, chests & signs and building chunks.
+/// Chunks are built on all cores, so `progress` may be called from several threads.
+pub fn read_with_progress(reader: &mut (impl Read + Seek), progress: impl Fn(Stage, u64, u64) + Sync) -> Result<World, VanillaError> {
 	// File format header.
 	debug!("Reading File Metadata");
 	
-	if reader.read_i32::<LittleEndian>()? != 230 { // Check Version
-		return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Wrong Vanilla World Version")));
+	let version = reader.read_i32::<LittleEndian>()?;
+	if !(MIN_VERSION..=MAX_VERSION).contains(&version) { // Check Version
+		return Err(VanillaError::UnsupportedVersion(version));
 	}
+	debug!("Vanilla World Version: {}", version);
+	
+	let (file, sections, tile_frame_important) = read_file_header(reader, version)?;
+	let mut world = World { file, ..World::default() };
+	
+	debug!("Reading World Header");
+	sections.read(reader, Section::WorldHeader, |reader| read_world_header(reader, version, &mut world))?;
+	
+	debug!("Reading Tiles");
+	let tiles = sections.read(reader, Section::Tiles, |reader| read_tiles(reader, &world, &tile_frame_important, &progress))?;
+	
+	debug!("Reading Chests");
+	let chests = sections.read(reader, Section::Chests, |reader| read_chests(reader, &progress))?;
+	
+	debug!("Reading Signs");
+	let signs = sections.read(reader, Section::Signs, |reader| read_signs(reader, &progress))?;
+	
+	debug!("Reading NPCs");
+	world.npcs = sections.read(reader, Section::Npcs, |reader| read_npcs(reader, version))?;
+	
+	debug!("Reading Tile Entities");
+	let tile_entities = sections.read(reader, Section::TileEntities, |reader| -> Result<_, SectionError> {
+		let mut tile_entities = Vec::new();
+		for _ in 0..reader.read_i32::<LittleEndian>()? {
+			tile_entities.push(TileEntity::read(reader)?);
+		}
+		Ok(tile_entities)
+	})?;
+	
+	let mut pressure_plates = Vec::new();
+	if version >= 170 {
+		debug!("Reading Pressure Plates");
+		pressure_plates = sections.read(reader, Section::PressurePlates, |reader| -> Result<_, SectionError> {
+			let mut plates = Vec::new();
+			for _ in 0..reader.read_i32::<LittleEndian>()? {
+				plates.push(TileCoord {
+					x: reader.read_i32::<LittleEndian>()? as u16,
+					y: reader.read_i32::<LittleEndian>()? as u16,
+				});
+			}
+			Ok(plates)
+		})?;
+	}
+	
+	let mut town_rooms = Vec::new();
+	if version >= 189 {
+		debug!("Reading Town Manager");
+		town_rooms = sections.read(reader, Section::TownManager, |reader| -> Result<_, SectionError> {
+			let mut rooms = Vec::new();
+			for _ in 0..reader.read_i32::<LittleEndian>()? {
+				let npc = reader.read_i32::<LittleEndian>()?;
+				let home = TileCoord {
+					x: reader.read_i32::<LittleEndian>()? as u16,
+					y: reader.read_i32::<LittleEndian>()? as u16,
+				};
+				rooms.push(TownRoom { npc, home });
+			}
+			Ok(rooms)
+		})?;
+	}
+	
+	if version >= 210 {
+		debug!("Reading Bestiary");
+		world.bestiary = sections.read(reader, Section::Bestiary, read_bestiary)?;
+	}
+	if version >= 220 {
+		debug!("Reading Creative Powers");
+		let end = sections.end(Section::CreativePowers);
+		world.creative_powers = sections.read(reader, Section::CreativePowers, |reader| read_creative_powers(reader, end))?;
+	}
+	
+	// Footer repeats the world name & id
+	debug!("Reading Footer");
+	let (name, id) = sections.read(reader, Section::Footer, |reader| -> Result<_, SectionError> {
+		if reader.read_u8()? == 0 { return Err("Footer is not marked valid".into()) }
+		Ok((reader.read_varstring()?, reader.read_i32::<LittleEndian>()?))
+	})?;
+	if name != world.name || id != world.id {
+		return Err(VanillaError::FooterMismatch { name, id, expected_name: world.name, expected_id: world.id });
+	}
+	
+	// Chunks.
+	debug!("Creating Chunks");
+	
+	world.chunk_size = DEFAULT_CHUNK_SIZE;
+	world.tile_frame_important = tile_frame_important;
+	let chunks = split_tiles(&world, &tiles, &progress);
+	world.chunks = build_chunks(&world, chunks, chests, signs, tile_entities, pressure_plates, town_rooms);
 
-	// File metadata.
-	reader.read_i64::<LittleEndian>()?; // Magic number and file type.
-	reader.read_u32::<LittleEndian>()?; // Revision.
-	reader.read_u64::<LittleEndian>()?; // Is favorite.
+	Ok(world)
+}
 
-	// Chunk offsets.
-	let chunk_count = reader.read_i16::<LittleEndian>()?;
-	for _ in 0..chunk_count {
-		reader.read_i32::<LittleEndian>()?;
+/// Reads the rest of the file format header (after the version), returns the file metadata, section offsets & which tile ids have frame data
+fn read_file_header(reader: &mut (impl Read + Seek), version: i32) -> Result<(FileInfo, SectionTable, Vec<bool>), VanillaError> {
+	let offset = reader.stream_position()?;
+	let parse_error = |source: io::Error| VanillaError::Parse { section: Section::FileHeader, offset, source: source.into() };
+	
+	// File metadata.
+	let mut magic = [0u8; 8];
+	reader.read_exact(&mut magic).map_err(parse_error)?;
+	if &magic[..7] != b"relogic" || magic[7] != 2 { return Err(VanillaError::NotAWorld) } // Magic number and file type (world).
+	let revision = reader.read_u32::<LittleEndian>().map_err(parse_error)?;
+	let is_favorite = reader.read_u64::<LittleEndian>().map_err(parse_error)? & 1 != 0;
+
+	// Section offsets.
+	let section_count = reader.read_i16::<LittleEndian>().map_err(parse_error)?;
+	let mut offsets = Vec::with_capacity(section_count.max(0) as usize);
+	for _ in 0..section_count {
+		offsets.push(reader.read_i32::<LittleEndian>().map_err(parse_error)? as u64);
 	}
+	let sections = SectionTable::new(version, offsets)?;
 
 	// Tile frame important.
-	let tile_count = reader.read_i16::<LittleEndian>()?;
-	let mut tile_frame_important = vec![false; tile_count as usize];
-	let mut byte = reader.read_u8()?;
-	let mut bit = 0b00000001;
-	for i in 0..tile_count as usize {
-		tile_frame_important[i] = byte & bit != 0;
-		if bit == 0b10000000 {
-			byte = reader.read_u8()?;
-			bit = 0b00000001;
-		} else {
-			bit <<= 1;
-		}
+	let tile_count = reader.read_i16::<LittleEndian>().map_err(parse_error)?;
+	let mut tile_frame_important = vec![false; tile_count.max(0) as usize];
+	let mut byte = 0;
+	for (i, important) in tile_frame_important.iter_mut().enumerate() {
+		if i % 8 == 0 { byte = reader.read_u8().map_err(parse_error)?; }
+		*important = byte & (1 << (i % 8)) != 0;
 	}
+	
+	let end = reader.stream_position()?;
+	let start = sections.offset(Section::WorldHeader)?;
+	if end != start {
+		return Err(VanillaError::SectionMismatch { section: Section::FileHeader, expected: start, found: end });
+	}
+	Ok((FileInfo { revision, is_favorite, creation_time: 0 }, sections, tile_frame_important)) // Creation time is in the world header
+}
 
-	// World header.
-	debug!("Reading World Header");
-
+fn read_world_header(reader: &mut impl Read, version: i32, world: &mut World) -> Result<(), SectionError> {
 	world.name = reader.read_varstring()?;
 	
-	let mut hasher = DefaultHasher::new();
-	reader.read_varstring()?.hash(&mut hasher);
-	world.gen_data.seed = hasher.finish();
-
-	reader.read_u64::<LittleEndian>()?; // Generator version.
-	world.uuid = reader.read_u128::<LittleEndian>()?;
+	if version >= 179 {
+		let seed = if version == 179 { reader.read_i32::<LittleEndian>()?.to_string() } else { reader.read_varstring()? };
+		let mut hasher = DefaultHasher::new();
+		seed.hash(&mut hasher);
+		world.gen_data.seed = hasher.finish();
+		world.gen_data.seed_text = seed;
+
+		world.gen_data.generator_version = reader.read_u64::<LittleEndian>()?;
+	} else {
+		world.gen_data.seed = random();
+	}
+	world.uuid = if version >= 181 { reader.read_u128::<LittleEndian>()? } else { random() };
 	world.id = reader.read_i32::<LittleEndian>()?;
 
 	world.dimensions.left = reader.read_i32::<LittleEndian>()? as u32;
@@ -80,17 +340,31 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 	world.dimensions.tile_height = reader.read_i32::<LittleEndian>()? as u32;
 	world.dimensions.tile_width = reader.read_i32::<LittleEndian>()? as u32;
 
-	world.worldmode = match reader.read_i32::<LittleEndian>()? {
-		1 => WorldMode::Expert,
-		2 => WorldMode::Master,
-		3 => WorldMode::Journey,
-		_ => WorldMode::Normal,
-	};
-
-	reader.read_u8()?; // Is Drunk? (has seed "05162020")
-	reader.read_u8()?; // Has seed "for the worthy"
+	if version >= 209 {
+		world.worldmode = match reader.read_i32::<LittleEndian>()? {
+			1 => WorldMode::Expert,
+			2 => WorldMode::Master,
+			3 => WorldMode::Journey,
+			_ => WorldMode::Normal,
+		};
+		// Secret seeds
+		let seeds = &mut world.gen_data.special_seeds;
+		if version >= 222 { seeds.drunk = reader.read_u8()? != 0; }
+		if version >= 227 { seeds.for_the_worthy = reader.read_u8()? != 0; }
+		if version >= 238 { seeds.celebration = reader.read_u8()? != 0; }
+		if version >= 239 { seeds.the_constant = reader.read_u8()? != 0; }
+		if version >= 241 { seeds.not_the_bees = reader.read_u8()? != 0; }
+		if version >= 249 { seeds.dont_dig_up = reader.read_u8()? != 0; }
+		if version >= 266 { seeds.no_traps = reader.read_u8()? != 0; }
+		if version >= 267 { seeds.get_fixed_boi = reader.read_u8()? != 0; }
+	} else {
+		let expert = reader.read_u8()? != 0;
+		let master = version == 208 && reader.read_u8()? != 0; // Version 208 saves master mode after expert mode
+		if master { world.worldmode = WorldMode::Master; }
+		else if expert { world.worldmode = WorldMode::Expert; }
+	}
 
-	reader.read_u64::<LittleEndian>()?; // Creation time.
+	world.file.creation_time = reader.read_i64::<LittleEndian>()?;
 
 	world.style.moon_style = reader.read_u8()?;
 
@@ -176,35 +450,25 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 	world.progress.altars_broken = reader.read_i32::<LittleEndian>()? as u32;
 
 	world.progress.is_hardmode = reader.read_u8()? != 0;
+	if version >= 257 { reader.read_u8()?; } // After party of doom.
 
 	world.events.invasion_delay = reader.read_i32::<LittleEndian>()?;
 	world.events.invasion_size = reader.read_i32::<LittleEndian>()?;
 	world.events.invasion_type = reader.read_i32::<LittleEndian>()? as u8;
 	world.events.invasion_x = reader.read_f64::<LittleEndian>()? as u32;
 
-	world.events.slime_rain_remaining_time = reader.read_f64::<LittleEndian>()? as i32; // version > 118
+	world.events.slime_rain_remaining_time = reader.read_f64::<LittleEndian>()? as i32;
 
-	world.time.sundial_cooldown = reader.read_u8()?; // version > 113
+	world.time.sundial_cooldown = reader.read_u8()?;
 
 	world.weather.is_raining = reader.read_u8()? != 0;
 	world.weather.rain_time = reader.read_i32::<LittleEndian>()? as u32;
 	world.weather.rain_amount = reader.read_f32::<LittleEndian>()?;
 	
-	world.gen_data.cobalt_tier = match reader.read_i32::<LittleEndian>()? {
-		107 => CobaltTier::CobaltOre,
-		221 => CobaltTier::PalladiumOre,
-		_ => random(), // Can sometimes be -1 if not in hardmode yet
-	};
-	world.gen_data.mythril_tier = match reader.read_i32::<LittleEndian>()? {
-		108 => MythrilTier::MythilOre,
-		222 => MythrilTier::OrichalcumOre,
-		_ => random(), // Can sometimes be -1 if not in hardmode yet
-	};
-	world.gen_data.adamantite_tier = match reader.read_i32::<LittleEndian>()? {
-		111 => AdamantiteTier::AdamantiteOre,
-		223 => AdamantiteTier::TitaniumOre,
-		_ => random(), // Can sometimes be -1 if not in hardmode yet
-	};
+	// Can sometimes be -1 if not in hardmode yet
+	world.gen_data.cobalt_tier = CobaltTier::try_from(reader.read_i32::<LittleEndian>()? as u16).unwrap_or_else(|_| random());
+	world.gen_data.mythril_tier = MythrilTier::try_from(reader.read_i32::<LittleEndian>()? as u16).unwrap_or_else(|_| random());
+	world.gen_data.adamantite_tier = AdamantiteTier::try_from(reader.read_i32::<LittleEndian>()? as u16).unwrap_or_else(|_| random());
 	world.style.forest_bg[0] = reader.read_u8()?;
 	world.style.corruption_bg = reader.read_u8()?;
 	world.style.jungle_bg = reader.read_u8()?;
@@ -231,7 +495,7 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 
 	world.progress.saved_stylist = reader.read_u8()? != 0;
 	world.progress.saved_tax_collector = reader.read_u8()? != 0;
-	world.progress.saved_golfer = reader.read_u8()? != 0;
+	if version >= 201 { world.progress.saved_golfer = reader.read_u8()? != 0; }
 
 	world.events.invasion_start_size = reader.read_i32::<LittleEndian>()?;
 
@@ -264,76 +528,117 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 	world.events.is_stardust_pillar_alive = reader.read_u8()? != 0;
 	world.events.is_impending_doom_approaching = reader.read_u8()? != 0;
 
-	world.events.is_party_manual = reader.read_u8()? != 0;
-	world.events.is_party_genuine = reader.read_u8()? != 0;
-	world.events.party_cooldown = reader.read_i32::<LittleEndian>()? as u8;
-	world
-		.events
-		.partying_npcs
-		.reserve_exact(reader.read_i32::<LittleEndian>()? as usize);
-	for _ in 0..world.events.partying_npcs.capacity() {
+	if version >= 170 {
+		world.events.is_party_manual = reader.read_u8()? != 0;
+		world.events.is_party_genuine = reader.read_u8()? != 0;
+		world.events.party_cooldown = reader.read_i32::<LittleEndian>()? as u8;
 		world
 			.events
 			.partying_npcs
-			.push(reader.read_i32::<LittleEndian>()? as u32);
+			.reserve_exact(reader.read_i32::<LittleEndian>()? as usize);
+		for _ in 0..world.events.partying_npcs.capacity() {
+			world
+				.events
+				.partying_npcs
+				.push(reader.read_i32::<LittleEndian>()? as u32);
+		}
 	}
 
-	world.weather.is_sandstorm_happening = reader.read_u8()? != 0;
-	world.weather.sandstorm_remaining_time = reader.read_i32::<LittleEndian>()?;
-	world.weather.sandstorm_severity = reader.read_f32::<LittleEndian>()?;
-	world.weather.sandstorm_intended_severity = reader.read_f32::<LittleEndian>()?;
-
-	world.progress.saved_bartender = reader.read_u8()? != 0;
+	if version >= 174 {
+		world.weather.is_sandstorm_happening = reader.read_u8()? != 0;
+		world.weather.sandstorm_remaining_time = reader.read_i32::<LittleEndian>()?;
+		world.weather.sandstorm_severity = reader.read_f32::<LittleEndian>()?;
+		world.weather.sandstorm_intended_severity = reader.read_f32::<LittleEndian>()?;
+	}
 
-	world.progress.defeated_old_ones_army_tier_1 = reader.read_u8()? != 0;
-	world.progress.defeated_old_ones_army_tier_2 = reader.read_u8()? != 0;
-	world.progress.defeated_old_ones_army_tier_3 = reader.read_u8()? != 0;
+	if version >= 178 {
+		world.progress.saved_bartender = reader.read_u8()? != 0;
 
-	world.style.mushroom_bg = reader.read_u8()?;
-	world.style.underworld_bg = reader.read_u8()?;
-	world.style.forest_bg[1] = reader.read_u8()?;
-	world.style.forest_bg[2] = reader.read_u8()?;
-	world.style.forest_bg[3] = reader.read_u8()?;
+		world.progress.defeated_old_ones_army_tier_1 = reader.read_u8()? != 0;
+		world.progress.defeated_old_ones_army_tier_2 = reader.read_u8()? != 0;
+		world.progress.defeated_old_ones_army_tier_3 = reader.read_u8()? != 0;
+	}
 
-	world.progress.used_combat_book = reader.read_u8()? != 0;
+	if version > 194 { world.style.mushroom_bg = reader.read_u8()?; }
+	if version >= 215 { world.style.underworld_bg = reader.read_u8()?; }
+	if version >= 195 {
+		world.style.forest_bg[1] = reader.read_u8()?;
+		world.style.forest_bg[2] = reader.read_u8()?;
+		world.style.forest_bg[3] = reader.read_u8()?;
+	} else { // Older worlds use the same forest background everywhere
+		let forest_bg = world.style.forest_bg[0];
+		world.style.forest_bg = [forest_bg; 4];
+	}
 
-	world.events.lantern_night_cooldown = reader.read_i32::<LittleEndian>()? as u8;
-	reader.read_u8()?; // Is lantern night genuine.
-	reader.read_u8()?; // Is lantern night manual.
-	world.events.is_lantern_night_queued = reader.read_u8()? != 0;
+	if version >= 204 { world.progress.used_combat_book = reader.read_u8()? != 0; }
 
-	for i in 0..reader.read_i32::<LittleEndian>()? as usize {
-		world.style.tree_tops[i] = reader.read_i32::<LittleEndian>()? as u8;
+	if version >= 207 {
+		world.events.lantern_night_cooldown = reader.read_i32::<LittleEndian>()? as u8;
+		world.events.is_lantern_night_genuine = reader.read_u8()? != 0;
+		world.events.is_lantern_night_manual = reader.read_u8()? != 0;
+		world.events.is_lantern_night_queued = reader.read_u8()? != 0;
 	}
 
-	world.events.is_halloween_forced = reader.read_u8()? != 0;
-	world.events.is_christmas_forced = reader.read_u8()? != 0;
+	if version >= 211 {
+		for i in 0..reader.read_i32::<LittleEndian>()? as usize {
+			let style = reader.read_i32::<LittleEndian>()? as u8;
+			if let Some(tree_top) = world.style.tree_tops.get_mut(i) { *tree_top = style; }
+		}
+	}
 
-	world.gen_data.copper_tier = CopperTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
-	world.gen_data.iron_tier = IronTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
-	world.gen_data.silver_tier = SilverTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
-	world.gen_data.gold_tier = GoldTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
+	if version >= 212 {
+		world.events.is_halloween_forced = reader.read_u8()? != 0;
+		world.events.is_christmas_forced = reader.read_u8()? != 0;
+	}
 
-	world.progress.purchased_cat = reader.read_u8()? != 0;
-	world.progress.purchased_dog = reader.read_u8()? != 0;
-	world.progress.purchased_bunny = reader.read_u8()? != 0;
+	if version >= 216 {
+		world.gen_data.copper_tier = CopperTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
+		world.gen_data.iron_tier = IronTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
+		world.gen_data.silver_tier = SilverTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
+		world.gen_data.gold_tier = GoldTier::try_from(reader.read_i32::<LittleEndian>()? as u16)?;
+	} else { // Older versions pick these when the world is loaded
+		world.gen_data.copper_tier = random();
+		world.gen_data.iron_tier = random();
+		world.gen_data.silver_tier = random();
+		world.gen_data.gold_tier = random();
+	}
 
-	world.progress.defeated_empress_of_light = reader.read_u8()? != 0;
-	world.progress.defeated_queen_slime = reader.read_u8()? != 0;
+	if version >= 217 {
+		world.progress.purchased_cat = reader.read_u8()? != 0;
+		world.progress.purchased_dog = reader.read_u8()? != 0;
+		world.progress.purchased_bunny = reader.read_u8()? != 0;
+	}
 
-	// Tiles.
-	debug!("reading tiles");
+	if version >= 223 {
+		world.progress.defeated_empress_of_light = reader.read_u8()? != 0;
+		world.progress.defeated_queen_slime = reader.read_u8()? != 0;
+	}
 	
+	// Fields added after 1.4.0.5 that terrarium doesn't keep yet
+	if version >= 240 { reader.read_u8()?; } // Defeated deerclops.
+	if version >= 250 { reader.read_u8()?; } // Unlocked blue slime.
+	if version >= 251 { for _ in 0..8 { reader.read_u8()?; } } // Unlocked town NPC spawns.
+	if version >= 259 { reader.read_u8()?; } // Used combat book volume two.
+	if version >= 260 { reader.read_u8()?; } // Used peddler's satchel.
+	if version >= 261 { for _ in 0..7 { reader.read_u8()?; } } // Unlocked town slimes.
+	if version >= 264 {
+		reader.read_u8()?; // Is fast forwarding to dusk.
+		reader.read_u8()?; // Moondial cooldown.
+	}
+	Ok(())
+}
+
+fn read_tiles(reader: &mut impl Read, world: &World, tile_frame_important: &[bool], progress: &impl Fn(Stage, u64, u64)) -> Result<Vec<Tile>, SectionError> {
 	let mut tiles = Vec::with_capacity(
 		world.dimensions.tile_width as usize * world.dimensions.tile_height as usize,
 	);
+	let mut shimmer_tiles = 0u64;
 	
 	for x in 0..world.dimensions.tile_width {
 		let mut y = 0;
 		while y < world.dimensions.tile_height {
 			let mut tile = Tile::default();
 			
-			let current_coord = TileCoord { x: x as u16, y: y as u16 };
 			let flags1 = reader.read_u8()?;
 			
 			if (flags1 & 0b00000010) != 0 {
@@ -355,11 +660,12 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 			let mut is_block_colored = false;
 			let mut is_wall_colored = false;
 			let mut is_wall_id_u16 = false;
+			let mut is_shimmer = false;
 
 			if (flags1 & 0b00000001) != 0 {
 				let flags2 = reader.read_u8()?; // Block metadata flags
 				
-				if let Some(mut block) = &mut tile.block { block.slope = (flags2 & 0b01110000) >> 4 } // Set slope
+				if let Some(block) = &mut tile.block { block.slope = (flags2 & 0b01110000) >> 4 } // Set slope
 
 				// Set wire states
 				tile.has_wire[0] = (flags2 & 0b00000010) != 0;
@@ -376,17 +682,24 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 					is_wall_colored = (flags3 & 0b00010000) != 0;
 					tile.has_wire[3] = (flags3 & 0b00100000) != 0;
 					is_wall_id_u16 = (flags3 & 0b01000000) != 0;
+					is_shimmer = (flags3 & 0b10000000) != 0; // Shimmer (1.4.4) is kept as water for now
+
+					if (flags3 & 0b00000001) != 0 {
+						reader.read_u8()?; // Echo & illuminant coatings (1.4.4), not kept yet
+					}
 				}
 			}
 			
 			if let Some(block) = &mut tile.block {
-				if (flags1 & 0b00100000) != 0 {
-					block.id = reader.read_u16::<LittleEndian>()?;
+				let block_id = if (flags1 & 0b00100000) != 0 {
+					reader.read_u16::<LittleEndian>()?
 				} else {
-					block.id = reader.read_u8()? as u16;
-				}
+					reader.read_u8()? as u16
+				};
+				block.id = block_id; // Kept even without a TileType, so newer tiles survive conversion
 
-				if tile_frame_important[block.id as usize] {
+				// Ids past the file's bitmap fall back to the tile registry
+				if tile_frame_important.get(block_id as usize).copied().unwrap_or_else(|| data::tiles().is_frame_important(block_id)) {
 					block.frame_width = reader.read_u16::<LittleEndian>()?;
 					block.frame_height = reader.read_u16::<LittleEndian>()?;
 				}
@@ -396,9 +709,9 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 				}
 			}
 			
+			let mut wall_id = 0;
 			if let Some(wall) = &mut tile.wall {
-				if let Some(id) = WallType::from_u16(reader.read_u8()? as u16) { wall.id = id }
-				else { warn!("Failed to read Wall ID"); }
+				wall_id = reader.read_u8()? as u16;
 				if is_wall_colored {
 					wall.color = reader.read_u8()?;
 				}
@@ -409,12 +722,10 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 			}
 			
 			if let Some(wall) = &mut tile.wall {
-				if is_wall_id_u16 {
-					let mut read_id = wall.id as u16;
-					read_id |= (reader.read_u8()? as u16) << 8;
-					if let Some(id) = WallType::from_u16(read_id) { wall.id = id; }
-					else { warn!("Failed to read Wall ID (type u16) found {:?} at tile: {:?}", read_id, current_coord); }
+				if is_wall_id_u16 { // High byte of the wall id comes after the liquid
+					wall_id |= (reader.read_u8()? as u16) << 8;
 				}
+				wall.id = wall_id; // Kept even without a WallType, so newer walls survive conversion
 			}
 
 			let mut repeat = 0;
@@ -425,16 +736,19 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 			}
 
 			y += repeat + 1;
+			if is_shimmer && tile.liquid.is_some() { shimmer_tiles += repeat as u64 + 1; }
 
 			for _ in 0..repeat + 1 {
 				tiles.push(tile);
 			}
 		}
+		progress(Stage::Tiles, x as u64 + 1, world.dimensions.tile_width as u64);
 	}
+	if shimmer_tiles != 0 { warn!("World has {} tiles of shimmer, which are converted to water", shimmer_tiles); }
+	Ok(tiles)
+}
 
-	// Chests.
-	debug!("Reading Chests");
-	
+fn read_chests(reader: &mut impl Read, progress: &impl Fn(Stage, u64, u64)) -> Result<Vec<Chest>, SectionError> {
 	let mut chests = Vec::with_capacity(reader.read_i16::<LittleEndian>()? as usize);
 
 	let item_count = reader.read_i16::<LittleEndian>()? as usize;
@@ -460,11 +774,97 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 		}
 
 		chests.push(chest);
+		progress(Stage::Chests, chests.len() as u64, chests.capacity() as u64);
+	}
+	Ok(chests)
+}
+
+/// Town NPCs, then mobs that are saved with the world
+fn read_npcs(reader: &mut impl Read, version: i32) -> Result<Vec<Npc>, SectionError> {
+	// Older worlds store NPCs by name
+	fn read_id(reader: &mut impl Read, version: i32) -> Result<Option<i32>, SectionError> {
+		if version >= 190 { return Ok(Some(reader.read_i32::<LittleEndian>()?)) }
+		let name = reader.read_varstring()?;
+		let id = npc::id_from_legacy_name(&name);
+		if id.is_none() { warn!("Skipping NPC with unknown name: {:?}", name); }
+		Ok(id)
 	}
 	
-	// Signs.
-	debug!("Reading Signs");
+	if version >= 268 { // Town NPCs that were shimmered (not kept yet)
+		for _ in 0..reader.read_i32::<LittleEndian>()? {
+			reader.read_i32::<LittleEndian>()?;
+		}
+	}
+	
+	let mut npcs = Vec::new();
+	while reader.read_u8()? != 0 {
+		let id = read_id(reader, version)?;
+		let name = reader.read_varstring()?;
+		let x = reader.read_f32::<LittleEndian>()?;
+		let y = reader.read_f32::<LittleEndian>()?;
+		let homeless = reader.read_u8()? != 0;
+		let home = TileCoord {
+			x: reader.read_i32::<LittleEndian>()? as u16,
+			y: reader.read_i32::<LittleEndian>()? as u16,
+		};
+		let mut variation = 0;
+		if version >= 213 && reader.read_u8()? & 0b00000001 != 0 {
+			variation = reader.read_i32::<LittleEndian>()?;
+		}
+		if let Some(id) = id {
+			npcs.push(Npc { id, x, y, resident: Some(Resident { name, home, homeless, variation }) });
+		}
+	}
+	
+	while reader.read_u8()? != 0 {
+		let id = read_id(reader, version)?;
+		let x = reader.read_f32::<LittleEndian>()?;
+		let y = reader.read_f32::<LittleEndian>()?;
+		if let Some(id) = id {
+			npcs.push(Npc { id, x, y, resident: None });
+		}
+	}
+	Ok(npcs)
+}
+
+fn read_bestiary(reader: &mut impl Read) -> Result<Bestiary, SectionError> {
+	let mut bestiary = Bestiary::default();
+	for _ in 0..reader.read_i32::<LittleEndian>()? {
+		let npc = reader.read_varstring()?;
+		bestiary.kills.push((npc, reader.read_i32::<LittleEndian>()? as u32));
+	}
+	for _ in 0..reader.read_i32::<LittleEndian>()? {
+		bestiary.seen.push(reader.read_varstring()?);
+	}
+	for _ in 0..reader.read_i32::<LittleEndian>()? {
+		bestiary.chatted.push(reader.read_varstring()?);
+	}
+	Ok(bestiary)
+}
+
+/// List of power ids followed by their state (bool toggles & f32 sliders)
+/// Powers after an unknown id are skipped (its state has an unknown size) by seeking to `end`
+fn read_creative_powers(reader: &mut (impl Read + Seek), end: Option<u64>) -> Result<CreativePowers, SectionError> {
+	let mut powers = CreativePowers::default();
+	while reader.read_u8()? != 0 {
+		match reader.read_u16::<LittleEndian>()? {
+			POWER_FREEZE_TIME => powers.freeze_time = reader.read_u8()? != 0,
+			POWER_TIME_SPEED => powers.time_speed = reader.read_f32::<LittleEndian>()?,
+			POWER_FREEZE_RAIN => powers.freeze_rain = reader.read_u8()? != 0,
+			POWER_FREEZE_WIND => powers.freeze_wind = reader.read_u8()? != 0,
+			POWER_DIFFICULTY => powers.difficulty = reader.read_f32::<LittleEndian>()?,
+			POWER_FREEZE_BIOME_SPREAD => powers.freeze_biome_spread = reader.read_u8()? != 0,
+			id => {
+				warn!("Unknown creative power id {}, skipping the rest of the creative powers", id);
+				if let Some(end) = end { reader.seek(SeekFrom::Start(end))?; }
+				break;
+			},
+		}
+	}
+	Ok(powers)
+}
 
+fn read_signs(reader: &mut impl Read, progress: &impl Fn(Stage, u64, u64)) -> Result<Vec<Sign>, SectionError> {
 	let mut signs = Vec::with_capacity(reader.read_i16::<LittleEndian>()? as usize);
 	
 	for _ in 0..signs.capacity() {
@@ -475,9 +875,782 @@ pub fn read(reader: &mut impl Read) -> Result<World, Box<dyn Error>> {
 		sign.y = reader.read_i32::<LittleEndian>()? as u32;
 
 		signs.push(sign);
+		progress(Stage::Signs, signs.len() as u64, signs.capacity() as u64);
 	}
-	let chunk_percent = 0.0;
-	debug!("Creating Chunks: {}", chunk_percent);
+	Ok(signs)
+}
 
-	Ok(world)
+/// Split column-ordered vanilla tiles into chunks of `world.chunk_size`.
+/// Every chunk_size columns of tiles become a column of chunks, which are built in parallel
+fn split_tiles(world: &World, tiles: &[Tile], progress: &(impl Fn(Stage, u64, u64) + Sync)) -> HashMap<ChunkCoord, Chunk> {
+	let chunk_size = world.chunk_size;
+	let tile_height = world.dimensions.tile_height as usize;
+	if tile_height == 0 { return HashMap::new() }
+	
+	let mut tiles = tiles;
+	if tiles.len() > world.dimensions.tile_width as usize * tile_height {
+		warn!("Tile data overflows world width");
+		tiles = &tiles[..world.dimensions.tile_width as usize * tile_height];
+	}
+	
+	let column_tiles = chunk_size as usize * tile_height;
+	let columns = tiles.len().div_ceil(column_tiles) as u64;
+	let done = AtomicU64::new(0);
+	tiles.par_chunks(column_tiles).enumerate().flat_map_iter(|(column, tiles)| {
+		let mut chunks: Vec<Option<Chunk>> = (0..tile_height.div_ceil(chunk_size as usize)).map(|_| None).collect();
+		for (i, &tile) in tiles.iter().enumerate() {
+			let coord = TileCoord { x: (column * chunk_size as usize + i / tile_height) as u16, y: (i % tile_height) as u16 };
+			let chunk = chunks[coord.y as usize / chunk_size as usize].get_or_insert_with(|| Chunk::new(
+				TileCoord { x: coord.x - coord.x % chunk_size, y: coord.y - coord.y % chunk_size },
+				chunk_size,
+			));
+			if let Some(chunk_tile) = chunk.get_tile_mut(&coord) { *chunk_tile = tile; }
+		}
+		progress(Stage::Chunks, done.fetch_add(1, Ordering::Relaxed) + 1, columns);
+		chunks.into_iter().flatten().map(move |chunk| (ChunkCoord::from_tilecoord(&chunk.start_pos, chunk_size), chunk))
+	}).collect()
+}
+
+/// Place chests, signs & other tile data into the chunks they're in
+fn build_chunks(world: &World, mut chunks: HashMap<ChunkCoord, Chunk>, chests: Vec<Chest>, signs: Vec<Sign>, tile_entities: Vec<TileEntity>, pressure_plates: Vec<TileCoord>, town_rooms: Vec<TownRoom>) -> HashMap<ChunkCoord, Chunk> {
+	let chunk_size = world.chunk_size;
+	
+	for (index, mut chest) in chests.into_iter().enumerate() {
+		chest.index = index as u16;
+		let coord = TileCoord { x: chest.x as u16, y: chest.y as u16 };
+		if let Some(chunk) = chunks.get_mut(&ChunkCoord::from_tilecoord(&coord, chunk_size)) {
+			chunk.chests.push(chest);
+		} else { warn!("Chest at {:?} is outside of world", coord); }
+	}
+	for (index, mut sign) in signs.into_iter().enumerate() {
+		sign.index = index as u16;
+		let coord = TileCoord { x: sign.x as u16, y: sign.y as u16 };
+		if let Some(chunk) = chunks.get_mut(&ChunkCoord::from_tilecoord(&coord, chunk_size)) {
+			chunk.signs.push(sign);
+		} else { warn!("Sign at {:?} is outside of world", coord); }
+	}
+	for entity in tile_entities {
+		let coord = entity.coord();
+		if let Some(chunk) = chunks.get_mut(&ChunkCoord::from_tilecoord(&coord, chunk_size)) {
+			chunk.tile_entities.push(entity);
+		} else { warn!("Tile entity at {:?} is outside of world", coord); }
+	}
+	for coord in pressure_plates {
+		if let Some(chunk) = chunks.get_mut(&ChunkCoord::from_tilecoord(&coord, chunk_size)) {
+			chunk.pressure_plates.push(coord);
+		} else { warn!("Pressure plate at {:?} is outside of world", coord); }
+	}
+	for room in town_rooms {
+		if let Some(chunk) = chunks.get_mut(&ChunkCoord::from_tilecoord(&room.home, chunk_size)) {
+			chunk.town_rooms.push(room);
+		} else { warn!("Town room at {:?} is outside of world", room.home); }
+	}
+	chunks
+}
+
+/// Write a world & its chunks as a vanilla (version 230) world that the vanilla client & TEdit can open.
+/// Tiles not in any chunk are written as empty tiles.
+pub fn write(world: &World, chunks: &HashMap<ChunkCoord, Chunk>, writer: &mut (impl Write + Seek)) -> Result<(), Box<dyn Error>> {
+	let mut tile_frame_important = world.tile_frame_important.clone();
+	if tile_frame_important.len() < TILE_COUNT { // Fill in ids the world doesn't know about from the tile registry
+		let registry = data::tiles().frame_important();
+		tile_frame_important.extend((tile_frame_important.len()..TILE_COUNT).map(|id| registry.get(id).copied().unwrap_or(false)));
+	}
+	
+	// File format header, section offsets are filled in once everything is written
+	debug!("Writing File Metadata");
+	let start = writer.stream_position()?;
+	write_file_header(writer, &world.file, &[0; SECTION_COUNT], &tile_frame_important)?;
+	
+	let mut sections = Vec::with_capacity(SECTION_COUNT);
+	
+	debug!("Writing World Header");
+	sections.push(offset(writer, start)?);
+	write_world_header(world, writer)?;
+	
+	debug!("Writing Tiles");
+	sections.push(offset(writer, start)?);
+	write_tiles(world, chunks, &tile_frame_important, writer)?;
+	
+	// Chests & signs are written in index order (clients refer to them by it)
+	let mut chests: Vec<&Chest> = chunks.values().flat_map(|chunk| chunk.chests.iter()).collect();
+	chests.sort_by_key(|chest| chest.index);
+	let mut signs: Vec<&Sign> = chunks.values().flat_map(|chunk| chunk.signs.iter()).collect();
+	signs.sort_by_key(|sign| sign.index);
+	
+	debug!("Writing Chests");
+	sections.push(offset(writer, start)?);
+	writer.write_i16::<LittleEndian>(chests.len() as i16)?;
+	writer.write_i16::<LittleEndian>(CHEST_SIZE as i16)?;
+	for chest in chests {
+		writer.write_i32::<LittleEndian>(chest.x as i32)?;
+		writer.write_i32::<LittleEndian>(chest.y as i32)?;
+		writer.write_varstring(&chest.name)?;
+		for i in 0..CHEST_SIZE {
+			match chest.items.get(i).filter(|item| item.stack > 0) {
+				Some(item) => {
+					writer.write_i16::<LittleEndian>(item.stack as i16)?;
+					writer.write_i32::<LittleEndian>(item.id as i32)?;
+					writer.write_u8(item.prefix)?;
+				},
+				None => writer.write_i16::<LittleEndian>(0)?,
+			}
+		}
+	}
+	
+	debug!("Writing Signs");
+	sections.push(offset(writer, start)?);
+	writer.write_i16::<LittleEndian>(signs.len() as i16)?;
+	for sign in signs {
+		writer.write_varstring(&sign.text)?;
+		writer.write_i32::<LittleEndian>(sign.x as i32)?;
+		writer.write_i32::<LittleEndian>(sign.y as i32)?;
+	}
+	
+	debug!("Writing NPCs");
+	sections.push(offset(writer, start)?);
+	for npc in world.npcs.iter() {
+		if let Some(resident) = &npc.resident {
+			writer.write_u8(1)?;
+			writer.write_i32::<LittleEndian>(npc.id)?;
+			writer.write_varstring(&resident.name)?;
+			writer.write_f32::<LittleEndian>(npc.x)?;
+			writer.write_f32::<LittleEndian>(npc.y)?;
+			writer.write_u8(resident.homeless as u8)?;
+			writer.write_i32::<LittleEndian>(resident.home.x as i32)?;
+			writer.write_i32::<LittleEndian>(resident.home.y as i32)?;
+			writer.write_u8((resident.variation != 0) as u8)?;
+			if resident.variation != 0 { writer.write_i32::<LittleEndian>(resident.variation)?; }
+		}
+	}
+	writer.write_u8(0)?; // End of town NPCs
+	for npc in world.npcs.iter().filter(|npc| npc.resident.is_none()) {
+		writer.write_u8(1)?;
+		writer.write_i32::<LittleEndian>(npc.id)?;
+		writer.write_f32::<LittleEndian>(npc.x)?;
+		writer.write_f32::<LittleEndian>(npc.y)?;
+	}
+	writer.write_u8(0)?; // End of mobs
+	sections.push(offset(writer, start)?);
+	let mut tile_entities: Vec<&TileEntity> = chunks.values().flat_map(|chunk| chunk.tile_entities.iter()).collect();
+	tile_entities.sort_by_key(|entity| entity.id);
+	writer.write_i32::<LittleEndian>(tile_entities.len() as i32)?;
+	for entity in tile_entities {
+		entity.write(writer)?;
+	}
+	sections.push(offset(writer, start)?);
+	let pressure_plates: Vec<&TileCoord> = chunks.values().flat_map(|chunk| chunk.pressure_plates.iter()).collect();
+	writer.write_i32::<LittleEndian>(pressure_plates.len() as i32)?;
+	for plate in pressure_plates {
+		writer.write_i32::<LittleEndian>(plate.x as i32)?;
+		writer.write_i32::<LittleEndian>(plate.y as i32)?;
+	}
+	sections.push(offset(writer, start)?);
+	let town_rooms: Vec<&TownRoom> = chunks.values().flat_map(|chunk| chunk.town_rooms.iter()).collect();
+	writer.write_i32::<LittleEndian>(town_rooms.len() as i32)?;
+	for room in town_rooms {
+		writer.write_i32::<LittleEndian>(room.npc)?;
+		writer.write_i32::<LittleEndian>(room.home.x as i32)?;
+		writer.write_i32::<LittleEndian>(room.home.y as i32)?;
+	}
+	sections.push(offset(writer, start)?);
+	let bestiary = &world.bestiary;
+	writer.write_i32::<LittleEndian>(bestiary.kills.len() as i32)?;
+	for (npc, kills) in bestiary.kills.iter() {
+		writer.write_varstring(npc)?;
+		writer.write_i32::<LittleEndian>(*kills as i32)?;
+	}
+	for list in [&bestiary.seen, &bestiary.chatted].iter() {
+		writer.write_i32::<LittleEndian>(list.len() as i32)?;
+		for npc in list.iter() {
+			writer.write_varstring(npc)?;
+		}
+	}
+	
+	sections.push(offset(writer, start)?);
+	let powers = &world.creative_powers;
+	for &(id, enabled) in [
+		(POWER_FREEZE_TIME, powers.freeze_time), (POWER_FREEZE_RAIN, powers.freeze_rain),
+		(POWER_FREEZE_WIND, powers.freeze_wind), (POWER_FREEZE_BIOME_SPREAD, powers.freeze_biome_spread),
+	].iter() {
+		writer.write_u8(1)?;
+		writer.write_u16::<LittleEndian>(id)?;
+		writer.write_u8(enabled as u8)?;
+	}
+	for &(id, value) in [(POWER_TIME_SPEED, powers.time_speed), (POWER_DIFFICULTY, powers.difficulty)].iter() {
+		writer.write_u8(1)?;
+		writer.write_u16::<LittleEndian>(id)?;
+		writer.write_f32::<LittleEndian>(value)?;
+	}
+	writer.write_u8(0)?; // End of creative powers
+	
+	// Footer
+	sections.push(offset(writer, start)?);
+	writer.write_u8(1)?;
+	writer.write_varstring(&world.name)?;
+	writer.write_i32::<LittleEndian>(world.id)?;
+	
+	let end = writer.stream_position()?;
+	writer.seek(SeekFrom::Start(start))?;
+	write_file_header(writer, &world.file, &sections, &tile_frame_important)?;
+	writer.seek(SeekFrom::Start(end))?;
+	Ok(())
+}
+/// Offset of the current position from the start of the file
+fn offset(writer: &mut impl Seek, start: u64) -> Result<i32, io::Error> {
+	Ok((writer.stream_position()? - start) as i32)
+}
+
+fn write_file_header(writer: &mut impl Write, file: &FileInfo, sections: &[i32], tile_frame_important: &[bool]) -> Result<(), io::Error> {
+	writer.write_i32::<LittleEndian>(VERSION)?;
+	writer.write_all(b"relogic")?;
+	writer.write_u8(2)?; // File type (world)
+	writer.write_u32::<LittleEndian>(file.revision)?;
+	writer.write_u64::<LittleEndian>(file.is_favorite as u64)?;
+	
+	writer.write_i16::<LittleEndian>(sections.len() as i16)?;
+	for &offset in sections {
+		writer.write_i32::<LittleEndian>(offset)?;
+	}
+	
+	writer.write_i16::<LittleEndian>(tile_frame_important.len() as i16)?;
+	for bits in tile_frame_important.chunks(8) {
+		let byte = bits.iter().enumerate().fold(0u8, |byte, (i, &important)| byte | (important as u8) << i);
+		writer.write_u8(byte)?;
+	}
+	Ok(())
+}
+
+fn write_world_header(world: &World, writer: &mut impl Write) -> Result<(), io::Error> {
+	writer.write_varstring(&world.name)?;
+	if world.gen_data.seed_text.is_empty() { // Not generated from a seed, fall back to the hash
+		writer.write_varstring(&world.gen_data.seed.to_string())?;
+	} else { writer.write_varstring(&world.gen_data.seed_text)?; }
+	writer.write_u64::<LittleEndian>(world.gen_data.generator_version)?;
+	writer.write_u128::<LittleEndian>(world.uuid)?;
+	writer.write_i32::<LittleEndian>(world.id)?;
+	
+	writer.write_i32::<LittleEndian>(world.dimensions.left as i32)?;
+	writer.write_i32::<LittleEndian>(world.dimensions.right as i32)?;
+	writer.write_i32::<LittleEndian>(world.dimensions.top as i32)?;
+	writer.write_i32::<LittleEndian>(world.dimensions.bottom as i32)?;
+	
+	writer.write_i32::<LittleEndian>(world.dimensions.tile_height as i32)?;
+	writer.write_i32::<LittleEndian>(world.dimensions.tile_width as i32)?;
+	
+	writer.write_i32::<LittleEndian>(world.worldmode as i32)?;
+	
+	writer.write_u8(world.gen_data.special_seeds.drunk as u8)?;
+	writer.write_u8(world.gen_data.special_seeds.for_the_worthy as u8)?; // Newer seeds don't exist in this version
+	
+	writer.write_i64::<LittleEndian>(world.file.creation_time)?;
+	
+	writer.write_u8(world.style.moon_style)?;
+	
+	for &x in world.style.forest_bg_x.iter() { writer.write_i32::<LittleEndian>(x as i32)?; }
+	for &style in world.style.forest_bg_style.iter() { writer.write_i32::<LittleEndian>(style as i32)?; }
+	for &x in world.style.cave_bg_x.iter() { writer.write_i32::<LittleEndian>(x as i32)?; }
+	for &style in world.style.cave_bg_style.iter() { writer.write_i32::<LittleEndian>(style as i32)?; }
+	
+	writer.write_i32::<LittleEndian>(world.style.snow_bg_style as i32)?;
+	writer.write_i32::<LittleEndian>(world.style.jungle_bg_style as i32)?;
+	writer.write_i32::<LittleEndian>(world.style.underworld_bg_style as i32)?;
+	
+	writer.write_i32::<LittleEndian>(world.spawn_coord.x as i32)?;
+	writer.write_i32::<LittleEndian>(world.spawn_coord.y as i32)?;
+	
+	writer.write_f64::<LittleEndian>(world.gen_data.surface_y as f64)?;
+	writer.write_f64::<LittleEndian>(world.gen_data.rock_layer_y as f64)?;
+	
+	writer.write_f64::<LittleEndian>(world.time.time as f64)?;
+	writer.write_u8(world.time.is_day as u8)?;
+	
+	writer.write_i32::<LittleEndian>(world.time.moon_phase as i32)?;
+	
+	writer.write_u8(world.events.is_blood_moon_happening as u8)?;
+	writer.write_u8(world.events.is_eclipse_happening as u8)?;
+	
+	writer.write_i32::<LittleEndian>(world.dungeon_coord.x as i32)?;
+	writer.write_i32::<LittleEndian>(world.dungeon_coord.y as i32)?;
+	
+	writer.write_u8(matches!(world.gen_data.corruption_type, CorruptionType::Crimson) as u8)?;
+	
+	let progress = &world.progress;
+	for &flag in [
+		progress.defeated_eye_of_cthulu, progress.defeated_eater_or_brain, progress.defeated_skeletron,
+		progress.defeated_queen_bee,
+		progress.defeated_destroyer, progress.defeated_twins, progress.defeated_skeletron_prime,
+		progress.defeated_destroyer || progress.defeated_twins || progress.defeated_skeletron_prime, // Downed any mech boss.
+		progress.defeated_plantera, progress.defeated_golem,
+		progress.defeated_king_slime,
+		progress.saved_goblin, progress.saved_wizard, progress.saved_mechanic,
+		progress.defeated_goblin_army,
+		progress.defeated_clown,
+		progress.defeated_frost_legion, progress.defeated_pirates,
+		progress.shadow_orbs_broken > 0, // Broken a shadow orb.
+		world.events.is_meteor_queued,
+	].iter() {
+		writer.write_u8(flag as u8)?;
+	}
+	
+	writer.write_u8(progress.shadow_orbs_broken)?;
+	writer.write_i32::<LittleEndian>(progress.altars_broken as i32)?;
+	
+	writer.write_u8(progress.is_hardmode as u8)?;
+	
+	writer.write_i32::<LittleEndian>(world.events.invasion_delay)?;
+	writer.write_i32::<LittleEndian>(world.events.invasion_size)?;
+	writer.write_i32::<LittleEndian>(world.events.invasion_type as i32)?;
+	writer.write_f64::<LittleEndian>(world.events.invasion_x as f64)?;
+	
+	writer.write_f64::<LittleEndian>(world.events.slime_rain_remaining_time as f64)?;
+	
+	writer.write_u8(world.time.sundial_cooldown)?;
+	
+	writer.write_u8(world.weather.is_raining as u8)?;
+	writer.write_i32::<LittleEndian>(world.weather.rain_time as i32)?;
+	writer.write_f32::<LittleEndian>(world.weather.rain_amount)?;
+	
+	writer.write_i32::<LittleEndian>(world.gen_data.cobalt_tier as i32)?;
+	writer.write_i32::<LittleEndian>(world.gen_data.mythril_tier as i32)?;
+	writer.write_i32::<LittleEndian>(world.gen_data.adamantite_tier as i32)?;
+	
+	writer.write_u8(world.style.forest_bg[0])?;
+	writer.write_u8(world.style.corruption_bg)?;
+	writer.write_u8(world.style.jungle_bg)?;
+	writer.write_u8(world.style.snow_bg)?;
+	writer.write_u8(world.style.hallow_bg)?;
+	writer.write_u8(world.style.crimson_bg)?;
+	writer.write_u8(world.style.desert_bg)?;
+	writer.write_u8(world.style.ocean_bg)?;
+	
+	writer.write_i32::<LittleEndian>(world.weather.active_cloud_bg)?;
+	writer.write_i16::<LittleEndian>(world.weather.num_clouds as i16)?;
+	
+	writer.write_f32::<LittleEndian>(world.weather.wind_speed)?;
+	
+	writer.write_i32::<LittleEndian>(world.angler.completed_quests.len() as i32)?;
+	for name in world.angler.completed_quests.iter() {
+		writer.write_varstring(name)?;
+	}
+	writer.write_u8(progress.saved_angler as u8)?;
+	writer.write_i32::<LittleEndian>(world.angler.quest as i32)?;
+	
+	writer.write_u8(progress.saved_stylist as u8)?;
+	writer.write_u8(progress.saved_tax_collector as u8)?;
+	writer.write_u8(progress.saved_golfer as u8)?;
+	
+	writer.write_i32::<LittleEndian>(world.events.invasion_start_size)?;
+	
+	writer.write_i32::<LittleEndian>(world.events.cultist_remaining_time)?;
+	
+	writer.write_i16::<LittleEndian>(progress.entity_kill_counts.len() as i16)?;
+	for &count in progress.entity_kill_counts.iter() {
+		writer.write_i32::<LittleEndian>(count as i32)?;
+	}
+	
+	writer.write_u8(world.time.is_fast_forwarding as u8)?;
+	
+	for &flag in [
+		progress.defeated_duke_fishron, progress.defeated_martians, progress.defeated_lunatic_cultist, progress.defeated_moon_lord,
+		progress.defeated_pumpking, progress.defeated_mourning_wood, progress.defeated_ice_queen, progress.defeated_santa_nk1, progress.defeated_everscream,
+		progress.defeated_solar_pillar, progress.defeated_vortex_pillar, progress.defeated_nebula_pillar, progress.defeated_stardust_pillar,
+		world.events.is_solar_pillar_alive, world.events.is_vortex_pillar_alive, world.events.is_nebula_pillar_alive, world.events.is_stardust_pillar_alive,
+		world.events.is_impending_doom_approaching,
+		world.events.is_party_manual, world.events.is_party_genuine,
+	].iter() {
+		writer.write_u8(flag as u8)?;
+	}
+	writer.write_i32::<LittleEndian>(world.events.party_cooldown as i32)?;
+	writer.write_i32::<LittleEndian>(world.events.partying_npcs.len() as i32)?;
+	for &npc in world.events.partying_npcs.iter() {
+		writer.write_i32::<LittleEndian>(npc as i32)?;
+	}
+	
+	writer.write_u8(world.weather.is_sandstorm_happening as u8)?;
+	writer.write_i32::<LittleEndian>(world.weather.sandstorm_remaining_time)?;
+	writer.write_f32::<LittleEndian>(world.weather.sandstorm_severity)?;
+	writer.write_f32::<LittleEndian>(world.weather.sandstorm_intended_severity)?;
+	
+	writer.write_u8(progress.saved_bartender as u8)?;
+	
+	writer.write_u8(progress.defeated_old_ones_army_tier_1 as u8)?;
+	writer.write_u8(progress.defeated_old_ones_army_tier_2 as u8)?;
+	writer.write_u8(progress.defeated_old_ones_army_tier_3 as u8)?;
+	
+	writer.write_u8(world.style.mushroom_bg)?;
+	writer.write_u8(world.style.underworld_bg)?;
+	writer.write_u8(world.style.forest_bg[1])?;
+	writer.write_u8(world.style.forest_bg[2])?;
+	writer.write_u8(world.style.forest_bg[3])?;
+	
+	writer.write_u8(progress.used_combat_book as u8)?;
+	
+	writer.write_i32::<LittleEndian>(world.events.lantern_night_cooldown as i32)?;
+	writer.write_u8(world.events.is_lantern_night_genuine as u8)?;
+	writer.write_u8(world.events.is_lantern_night_manual as u8)?;
+	writer.write_u8(world.events.is_lantern_night_queued as u8)?;
+	
+	writer.write_i32::<LittleEndian>(world.style.tree_tops.len() as i32)?;
+	for &style in world.style.tree_tops.iter() {
+		writer.write_i32::<LittleEndian>(style as i32)?;
+	}
+	
+	writer.write_u8(world.events.is_halloween_forced as u8)?;
+	writer.write_u8(world.events.is_christmas_forced as u8)?;
+	
+	writer.write_i32::<LittleEndian>(world.gen_data.copper_tier as i32)?;
+	writer.write_i32::<LittleEndian>(world.gen_data.iron_tier as i32)?;
+	writer.write_i32::<LittleEndian>(world.gen_data.silver_tier as i32)?;
+	writer.write_i32::<LittleEndian>(world.gen_data.gold_tier as i32)?;
+	
+	writer.write_u8(progress.purchased_cat as u8)?;
+	writer.write_u8(progress.purchased_dog as u8)?;
+	writer.write_u8(progress.purchased_bunny as u8)?;
+	
+	writer.write_u8(progress.defeated_empress_of_light as u8)?;
+	writer.write_u8(progress.defeated_queen_slime as u8)?;
+	Ok(())
+}
+
+/// Tiles are written column by column, runs of identical tiles in a column are written once with a repeat count
+fn write_tiles(world: &World, chunks: &HashMap<ChunkCoord, Chunk>, tile_frame_important: &[bool], writer: &mut impl Write) -> Result<(), io::Error> {
+	let chunk_size = if world.chunk_size == 0 { DEFAULT_CHUNK_SIZE } else { world.chunk_size };
+	let empty = Tile::default();
+	
+	for x in 0..world.dimensions.tile_width as u16 {
+		let mut chunk: Option<(ChunkCoord, Option<&Chunk>)> = None;
+		let mut last: Option<(&Tile, Vec<u8>, Vec<u8>)> = None;
+		let mut repeat = 0u16;
+		for y in 0..world.dimensions.tile_height as u16 {
+			let coord = TileCoord { x, y };
+			let chunk_coord = ChunkCoord::from_tilecoord(&coord, chunk_size);
+			let current = match chunk {
+				Some((last_coord, current)) if last_coord == chunk_coord => current,
+				_ => {
+					let current = chunks.get(&chunk_coord);
+					chunk = Some((chunk_coord, current));
+					current
+				},
+			};
+			let tile = current.and_then(|chunk| chunk.get_tile(&coord)).unwrap_or(&empty);
+			
+			if let Some((last_tile, ..)) = &last {
+				if *last_tile == tile { repeat += 1; continue; }
+			}
+			if let Some((_, header, data)) = last.take() {
+				write_packed_tile(writer, header, &data, repeat)?;
+			}
+			let (header, data) = tile.pack(tile_frame_important);
+			last = Some((tile, header, data));
+			repeat = 0;
+		}
+		if let Some((_, header, data)) = last.take() {
+			write_packed_tile(writer, header, &data, repeat)?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	use crate::world::{GoldTier, chunk::TileEntityKind};
+
+	fn test_world() -> World {
+		let mut world = World {
+			name: "Vanilla Test".into(), id: 1234, uuid: 0xDEADBEEF,
+			chunk_size: DEFAULT_CHUNK_SIZE,
+			spawn_coord: TileCoord { x: 20, y: 10 },
+			..World::default()
+		};
+		world.dimensions.tile_width = 40;
+		world.dimensions.tile_height = 30;
+		world.worldmode = WorldMode::Master;
+		world.gen_data.corruption_type = CorruptionType::Crimson;
+		world.gen_data.gold_tier = GoldTier::PlatinumOre;
+		world.gen_data.seed_text = "05162020".into();
+		world.gen_data.generator_version = 1_099_511_627_777;
+		world.gen_data.special_seeds.drunk = true;
+		world.file = FileInfo { revision: 7, is_favorite: true, creation_time: 0x08D8_0000_1234_5678 };
+		world.events.is_lantern_night_manual = true;
+		world.progress.defeated_twins = true;
+		world.progress.shadow_orbs_broken = 2;
+		world.progress.entity_kill_counts = vec![0, 5, 12];
+		world.angler.completed_quests = vec!["Tester".into()];
+		world.bestiary.kills = vec![("BlueSlime".into(), 12), ("Zombie".into(), 3)];
+		world.bestiary.seen = vec!["DemonEye".into()];
+		world.bestiary.chatted = vec!["Guide".into(), "Merchant".into()];
+		world.creative_powers.freeze_time = true;
+		world.creative_powers.freeze_biome_spread = true;
+		world.creative_powers.time_speed = 0.25;
+		world.creative_powers.difficulty = 0.75;
+		world.time.time = 5000;
+		world.style.tree_tops[4] = 3;
+		world.tile_frame_important = vec![false; TILE_COUNT];
+		world.tile_frame_important[21] = true; // Chests
+		world.npcs = vec![
+			Npc { id: 22, x: 320.0, y: 304.0, resident: Some(Resident { name: "Andrew".into(), home: TileCoord { x: 20, y: 18 }, homeless: false, variation: 0 }) },
+			Npc { id: 17, x: 400.0, y: 304.0, resident: Some(Resident { name: "Brandon".into(), home: TileCoord { x: 25, y: 18 }, homeless: true, variation: 1 }) },
+			Npc { id: 517, x: 160.0, y: 96.0, resident: None },
+		];
+		
+		let mut chunk = Chunk::new(TileCoord { x: 16, y: 16 }, DEFAULT_CHUNK_SIZE);
+		for x in 16..32 {
+			*chunk.get_tile_mut(&TileCoord { x, y: 20 }).unwrap() = Tile::new(Block { color: 4, ..Block::new(TileType::Stone) });
+		}
+		*chunk.get_tile_mut(&TileCoord { x: 17, y: 19 }).unwrap() = Tile::new(Block { frame_width: 18, frame_height: 0, ..Block::new(TileType::Containers) });
+		*chunk.get_tile_mut(&TileCoord { x: 20, y: 19 }).unwrap() = Tile {
+			block: Some(Block { slope: 2, is_inactive: true, ..Block::new(TileType::BoneWelder) }),
+			wall: Some(Wall { color: 7, ..Wall::new(WallType::Wood) }),
+			has_wire: [true, false, false, true],
+			has_actuator: true,
+			..Tile::default()
+		};
+		*chunk.get_tile_mut(&TileCoord { x: 22, y: 25 }).unwrap() = Tile { liquid: Some(Liquid { id: 2, amount: 255 }), ..Tile::default() };
+		*chunk.get_tile_mut(&TileCoord { x: 24, y: 25 }).unwrap() = Tile::new(Block { id: 690, ..Block::default() }); // Tile from a newer version
+		chunk.get_tile_mut(&TileCoord { x: 26, y: 25 }).unwrap().wall = Some(Wall { id: 400, color: 0 }); // Wall from a newer version
+		chunk.chests.push(Chest {
+			index: 0, name: "Loot".into(), x: 17, y: 19,
+			items: vec![ItemStack { stack: 3, id: 29, prefix: 0 }, ItemStack { stack: 1, id: 4956, prefix: 81 }],
+		});
+		chunk.signs.push(Sign { index: 0, x: 25, y: 19, text: "Hello\nWorld".into() });
+		let item = |id| Some(ItemStack { stack: 1, id, prefix: 0 });
+		chunk.tile_entities = vec![
+			TileEntity { id: 0, x: 18, y: 17, kind: TileEntityKind::TrainingDummy { npc: -1 } },
+			TileEntity { id: 1, x: 19, y: 17, kind: TileEntityKind::ItemFrame(ItemStack { stack: 5, id: 75, prefix: 0 }) },
+			TileEntity { id: 2, x: 21, y: 17, kind: TileEntityKind::LogicSensor { check: 3, on: true } },
+			TileEntity { id: 3, x: 22, y: 16, kind: TileEntityKind::DisplayDoll { items: [item(88), None, item(90), None, None, None, None, item(5)], dyes: [None, item(1007), None, None, None, None, None, None] } },
+			TileEntity { id: 4, x: 24, y: 16, kind: TileEntityKind::WeaponsRack(ItemStack { stack: 1, id: 4956, prefix: 81 }) },
+			TileEntity { id: 5, x: 26, y: 16, kind: TileEntityKind::HatRack { items: [None, item(239)], dyes: [item(1008), None] } },
+			TileEntity { id: 6, x: 28, y: 18, kind: TileEntityKind::FoodPlatter(ItemStack { stack: 1, id: 4011, prefix: 0 }) },
+			TileEntity { id: 7, x: 29, y: 16, kind: TileEntityKind::TeleportationPylon },
+		];
+		chunk.pressure_plates.push(TileCoord { x: 30, y: 19 });
+		chunk.town_rooms.push(TownRoom { npc: 22, home: TileCoord { x: 20, y: 18 } });
+		world.chunks.insert(ChunkCoord { x: 1, y: 1 }, chunk);
+		world
+	}
+	fn round_trip(world: &World) -> World {
+		read(&mut Cursor::new(written(world))).unwrap()
+	}
+
+	#[test]
+	fn world_header_round_trip() {
+		let world = test_world();
+		let read = round_trip(&world);
+		
+		assert_eq!(read.name, world.name);
+		assert_eq!((read.id, read.uuid), (world.id, world.uuid));
+		assert_eq!((read.dimensions.tile_width, read.dimensions.tile_height), (40, 30));
+		assert_eq!(read.spawn_coord, world.spawn_coord);
+		assert!(matches!(read.worldmode, WorldMode::Master));
+		assert!(matches!(read.gen_data.corruption_type, CorruptionType::Crimson));
+		assert!(matches!(read.gen_data.gold_tier, GoldTier::PlatinumOre));
+		assert!(read.progress.defeated_twins && !read.progress.defeated_destroyer);
+		assert_eq!(read.progress.shadow_orbs_broken, 2);
+		assert_eq!(read.progress.entity_kill_counts, world.progress.entity_kill_counts);
+		assert_eq!(read.angler.completed_quests, world.angler.completed_quests);
+		assert_eq!(read.time.time, 5000);
+		assert_eq!(read.style.tree_tops, world.style.tree_tops);
+		assert_eq!(read.tile_frame_important, world.tile_frame_important);
+	}
+	#[test]
+	fn header_metadata_round_trip() {
+		let world = test_world();
+		let read = round_trip(&world);
+		
+		assert_eq!(read.file, world.file);
+		assert_eq!(read.gen_data.seed_text, "05162020");
+		assert_eq!(read.gen_data.generator_version, world.gen_data.generator_version);
+		assert_eq!(read.gen_data.special_seeds, world.gen_data.special_seeds);
+		assert!(read.events.is_lantern_night_manual && !read.events.is_lantern_night_genuine);
+	}
+	#[test]
+	fn tiles_round_trip() {
+		let world = test_world();
+		let read = round_trip(&world);
+		
+		let empty = Tile::default();
+		for x in 0..40 {
+			for y in 0..30 {
+				let coord = TileCoord { x, y };
+				let chunk_coord = ChunkCoord::from_tilecoord(&coord, DEFAULT_CHUNK_SIZE);
+				let expected = world.chunks.get(&chunk_coord).and_then(|chunk| chunk.get_tile(&coord)).unwrap_or(&empty);
+				let tile = read.chunks[&chunk_coord].get_tile(&coord).unwrap();
+				assert_eq!(tile, expected, "Tile at {:?}", coord);
+			}
+		}
+		let unknown = read.chunks[&ChunkCoord { x: 1, y: 1 }].get_tile(&TileCoord { x: 24, y: 25 }).unwrap().block.unwrap();
+		assert_eq!((unknown.id, unknown.tile_type()), (690, None));
+		let unknown = read.chunks[&ChunkCoord { x: 1, y: 1 }].get_tile(&TileCoord { x: 26, y: 25 }).unwrap().wall.unwrap();
+		assert_eq!((unknown.id, unknown.wall_type()), (400, None));
+	}
+	#[test]
+	fn chests_and_signs_round_trip() {
+		let world = test_world();
+		let read = round_trip(&world);
+		
+		let chunk = &read.chunks[&ChunkCoord { x: 1, y: 1 }];
+		assert_eq!(chunk.chests.len(), 1);
+		let chest = &chunk.chests[0];
+		assert_eq!((chest.name.as_str(), chest.x, chest.y), ("Loot", 17, 19));
+		assert_eq!(chest.items.len(), CHEST_SIZE);
+		assert_eq!((chest.items[1].stack, chest.items[1].id, chest.items[1].prefix), (1, 4956, 81));
+		assert_eq!(chest.items[2].stack, 0);
+		
+		assert_eq!(chunk.signs.len(), 1);
+		assert_eq!((chunk.signs[0].text.as_str(), chunk.signs[0].x, chunk.signs[0].y), ("Hello\nWorld", 25, 19));
+	}
+	#[test]
+	fn npcs_round_trip() {
+		let world = test_world();
+		assert_eq!(round_trip(&world).npcs, world.npcs);
+	}
+	#[test]
+	fn tile_data_round_trip() {
+		let world = test_world();
+		let read = round_trip(&world);
+		
+		let (chunk, expected) = (&read.chunks[&ChunkCoord { x: 1, y: 1 }], &world.chunks[&ChunkCoord { x: 1, y: 1 }]);
+		assert_eq!(chunk.tile_entities, expected.tile_entities);
+		assert_eq!(chunk.pressure_plates, expected.pressure_plates);
+		assert_eq!(chunk.town_rooms, expected.town_rooms);
+	}
+	#[test]
+	fn bestiary_and_creative_powers_round_trip() {
+		let world = test_world();
+		let read = round_trip(&world);
+		
+		assert_eq!(read.bestiary.kills, world.bestiary.kills);
+		assert_eq!(read.bestiary.seen, world.bestiary.seen);
+		assert_eq!(read.bestiary.chatted, world.bestiary.chatted);
+		let (powers, expected) = (&read.creative_powers, &world.creative_powers);
+		assert_eq!((powers.freeze_time, powers.freeze_rain, powers.freeze_wind, powers.freeze_biome_spread), (true, false, false, true));
+		assert_eq!((powers.time_speed, powers.difficulty), (expected.time_speed, expected.difficulty));
+	}
+	#[test]
+	fn every_saved_creative_power_is_read() {
+		let mut data = Vec::new();
+		for &(id, state) in [(POWER_FREEZE_TIME, &[1][..]), (POWER_FREEZE_RAIN, &[1]), (POWER_FREEZE_WIND, &[1]), (POWER_FREEZE_BIOME_SPREAD, &[1])].iter() {
+			data.write_u8(1).unwrap();
+			data.write_u16::<LittleEndian>(id).unwrap();
+			data.extend_from_slice(state);
+		}
+		for &(id, value) in [(POWER_TIME_SPEED, 0.5), (POWER_DIFFICULTY, 1.0)].iter() {
+			data.write_u8(1).unwrap();
+			data.write_u16::<LittleEndian>(id).unwrap();
+			data.write_f32::<LittleEndian>(value).unwrap();
+		}
+		data.write_u8(0).unwrap();
+		
+		let powers = read_creative_powers(&mut Cursor::new(data), None).unwrap();
+		assert!(powers.freeze_time && powers.freeze_rain && powers.freeze_wind && powers.freeze_biome_spread);
+		assert_eq!((powers.time_speed, powers.difficulty), (0.5, 1.0));
+	}
+	#[test]
+	fn unknown_creative_power_skips_to_section_end() {
+		let data = [1, 0, 0, 1, 1, 7, 0, 1, 1, 13, 0, 1, 0]; // Power 7 (rain, per player) isn't saved by vanilla
+		let mut reader = Cursor::new(&data[..]);
+		let powers = read_creative_powers(&mut reader, Some(13)).unwrap();
+		assert!(powers.freeze_time && !powers.freeze_biome_spread); // Powers before the unknown one are kept
+		assert_eq!(reader.position(), 13);
+	}
+	#[test]
+	fn progress_reaches_totals() {
+		let world = test_world();
+		let reports = std::sync::Mutex::new(Vec::new());
+		read_with_progress(&mut Cursor::new(written(&world)), |stage, done, total| reports.lock().unwrap().push((stage, done, total))).unwrap();
+		
+		let reports = reports.into_inner().unwrap();
+		let columns = (world.dimensions.tile_width as u64).div_ceil(DEFAULT_CHUNK_SIZE as u64);
+		for &(stage, total) in [(Stage::Tiles, 40), (Stage::Chests, 1), (Stage::Signs, 1), (Stage::Chunks, columns)].iter() {
+			let done: Vec<u64> = reports.iter().filter(|report| report.0 == stage).map(|report| report.1).collect();
+			assert_eq!(done.len() as u64, total, "{} reports", stage);
+			assert_eq!(done.iter().max(), Some(&total), "{} progress", stage);
+			assert!(reports.iter().filter(|report| report.0 == stage).all(|report| report.2 == total));
+		}
+	}
+	#[test]
+	fn unsupported_versions_are_rejected() {
+		for &version in [MIN_VERSION - 1, MAX_VERSION + 1].iter() {
+			let mut data = Vec::new();
+			data.write_i32::<LittleEndian>(version).unwrap();
+			data.extend_from_slice(&[0; 64]);
+			assert!(matches!(read(&mut Cursor::new(data)), Err(VanillaError::UnsupportedVersion(v)) if v == version));
+		}
+	}
+	#[test]
+	fn version_208_reads_master_mode() {
+		// Start of a world header up to the creation time, which has to be read from the right position
+		let header = |version: i32, modes: &[u8]| {
+			let mut data = Vec::new();
+			data.write_varstring("Old World").unwrap();
+			data.write_varstring("1234").unwrap(); // Seed
+			data.extend_from_slice(&[0; 8 + 16 + 4 + 4 * 6]); // Generator version, uuid, id, bounds & size
+			data.extend_from_slice(modes);
+			data.write_i64::<LittleEndian>(0x1234).unwrap();
+			let mut world = World::default();
+			let _ = read_world_header(&mut Cursor::new(data), version, &mut world); // Fails at the end of the data
+			world
+		};
+		let world = header(208, &[1, 1]);
+		assert!(matches!(world.worldmode, WorldMode::Master));
+		assert_eq!(world.file.creation_time, 0x1234);
+		let world = header(208, &[1, 0]);
+		assert!(matches!(world.worldmode, WorldMode::Expert));
+		assert_eq!(world.file.creation_time, 0x1234);
+		let world = header(207, &[1]);
+		assert!(matches!(world.worldmode, WorldMode::Expert));
+		assert_eq!(world.file.creation_time, 0x1234);
+	}
+	fn written(world: &World) -> Vec<u8> {
+		let mut writer = Cursor::new(Vec::new());
+		write(world, &world.chunks, &mut writer).unwrap();
+		writer.into_inner()
+	}
+	#[test]
+	fn wrong_magic_is_rejected() {
+		let mut data = written(&test_world());
+		data[4..11].copy_from_slice(b"notrelo");
+		assert!(matches!(read(&mut Cursor::new(data)), Err(VanillaError::NotAWorld)));
+	}
+	#[test]
+	fn wrong_section_offset_names_section() {
+		let mut data = written(&test_world());
+		// Offsets start after version, magic, revision, favorite & count, chests are the third section
+		let chests = 4 + 8 + 4 + 8 + 2 + 2 * 4;
+		let offset = i32::from_le_bytes(data[chests..chests + 4].try_into().unwrap());
+		data[chests..chests + 4].copy_from_slice(&(offset + 1).to_le_bytes());
+		match read(&mut Cursor::new(data)) {
+			Err(VanillaError::SectionMismatch { section: Section::Tiles, expected, found }) => assert_eq!(expected, found + 1),
+			other => panic!("Expected tiles section mismatch, got {:?}", other.map(|world| world.name)),
+		}
+	}
+	#[test]
+	fn footer_mismatch_is_detected() {
+		let world = test_world();
+		let mut data = written(&world);
+		let len = data.len();
+		data[len - 4..].copy_from_slice(&(world.id + 1).to_le_bytes()); // World id is the last field
+		assert!(matches!(read(&mut Cursor::new(data)), Err(VanillaError::FooterMismatch { id, .. }) if id == world.id + 1));
+	}
+	#[test]
+	fn truncated_file_names_section() {
+		let mut data = written(&test_world());
+		data.truncate(data.len() - 8); // Cut into the footer
+		assert!(matches!(read(&mut Cursor::new(data)), Err(VanillaError::Parse { section: Section::Footer, .. })));
+	}
+	#[test]
+	fn rewrite_is_stable() {
+		// Everything survives a second round trip unchanged
+		let first = round_trip(&test_world());
+		let second = round_trip(&first);
+		
+		let (mut a, mut b) = (Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+		write(&first, &first.chunks, &mut a).unwrap();
+		write(&second, &second.chunks, &mut b).unwrap();
+		assert_eq!(a.into_inner(), b.into_inner());
+	}
 }
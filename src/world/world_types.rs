@@ -72,55 +72,60 @@ pub mod ore_tiers {
 	use serde::{Serialize, Deserialize};
 	use num_enum::TryFromPrimitive;
 	use rand_enum_derive::EnumRand;
+	use crate::world::chunk::tile::TileType;
+	// Tiers are stored by the tile id of their ore
 	#[derive(Debug, Deserialize, Serialize, TryFromPrimitive, EnumRand, Clone, Copy)]
 	#[repr(u16)]
 	pub enum CopperTier {
-		CopperOre = 7,
-		TinOre = 166,
+		CopperOre = TileType::Copper as u16,
+		TinOre = TileType::Tin as u16,
 	}
 	#[derive(Debug, Deserialize, Serialize, TryFromPrimitive, EnumRand, Clone, Copy)]
 	#[repr(u16)]
 	pub enum IronTier {
-		IronOre = 6,
-		LeadOre = 167,
+		IronOre = TileType::Iron as u16,
+		LeadOre = TileType::Lead as u16,
 	}
 	//use crate::world::test::IronTier;
 	#[derive(Debug, Deserialize, Serialize, TryFromPrimitive, EnumRand, Clone, Copy)]
 	#[repr(u16)]
 	pub enum SilverTier {
-		SilverOre = 9,
-		TungstenOre = 168,
+		SilverOre = TileType::Silver as u16,
+		TungstenOre = TileType::Tungsten as u16,
 	}
 	#[derive(Debug, Deserialize, Serialize, TryFromPrimitive, EnumRand, Clone, Copy)]
 	#[repr(u16)]
 	pub enum GoldTier {
-		GoldOre = 8,
-		PlatinumOre = 166,
+		GoldOre = TileType::Gold as u16,
+		PlatinumOre = TileType::Platinum as u16,
 	}
 	#[derive(Debug, Deserialize, Serialize, TryFromPrimitive, EnumRand, Clone, Copy)]
 	#[repr(u16)]
 	pub enum CobaltTier {
-		CobaltOre = 107,
-		PalladiumOre = 221,
+		CobaltOre = TileType::Cobalt as u16,
+		PalladiumOre = TileType::Palladium as u16,
 	}
 	#[derive(Debug, Deserialize, Serialize, TryFromPrimitive, EnumRand, Clone, Copy)]
 	#[repr(u16)]
 	pub enum MythrilTier {
-		MythilOre = 108,
-		OrichalcumOre = 222,
+		MythilOre = TileType::Mythril as u16,
+		OrichalcumOre = TileType::Orichalcum as u16,
 	}
 	#[derive(Debug, Deserialize, Serialize, TryFromPrimitive, EnumRand, Clone, Copy)]
 	#[repr(u16)]
 	pub enum AdamantiteTier {
-		AdamantiteOre = 111,
-		TitaniumOre = 223,
+		AdamantiteOre = TileType::Adamantite as u16,
+		TitaniumOre = TileType::Titanium as u16,
 	}
 }
 pub use ore_tiers::*;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GenerationData {
-	pub seed: u64,
+	pub seed: u64, // Hash of seed_text
+	pub seed_text: String, // Seed as entered, empty if the world wasn't generated from one
+	pub generator_version: u64, // Version of the generator that created the world (vanilla worlds)
+	pub special_seeds: SpecialSeeds,
 	pub corruption_type: CorruptionType,
 	pub surface_y: u32,
 	pub rock_layer_y: u32,
@@ -136,7 +141,7 @@ pub struct GenerationData {
 impl Default for GenerationData {
 	fn default() -> GenerationData {
 		GenerationData {
-			seed: 0,
+			seed: 0, seed_text: String::new(), generator_version: 0, special_seeds: SpecialSeeds::default(),
 			corruption_type: CorruptionType::Corruption,
 			surface_y: 0, rock_layer_y: 0,
 			copper_tier: CopperTier::CopperOre, iron_tier: IronTier::IronOre, silver_tier: SilverTier::SilverOre, gold_tier: GoldTier::GoldOre,
@@ -145,6 +150,27 @@ impl Default for GenerationData {
 	}
 }
 
+/// Secret seeds the world was generated with, clients change some behavior for them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct SpecialSeeds {
+	pub drunk: bool, // "05162020"
+	pub for_the_worthy: bool,
+	pub celebration: bool, // "celebrationmk10"
+	pub the_constant: bool,
+	pub not_the_bees: bool,
+	pub dont_dig_up: bool,
+	pub no_traps: bool,
+	pub get_fixed_boi: bool,
+}
+
+/// Metadata of the file a world was imported from, kept so it can be exported unchanged
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FileInfo {
+	pub revision: u32, // Times the world was saved
+	pub is_favorite: bool,
+	pub creation_time: i64, // .NET DateTime in binary form
+}
+
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Progress {
 	// Bosses.
@@ -264,6 +290,8 @@ pub struct Events {
 
 	// Lantern night.
 	pub lantern_night_cooldown: u8,
+	pub is_lantern_night_genuine: bool,
+	pub is_lantern_night_manual: bool,
 	pub is_lantern_night_queued: bool,
 
 	// Lunar events.
@@ -284,4 +312,23 @@ pub struct Events {
 pub struct Angler {
 	pub completed_quests: Vec<String>,
 	pub quest: u8,
-}
\ No newline at end of file
+}
+
+/// Which NPCs players have killed, seen & talked to (by bestiary id, e.g. "BlueSlime")
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Bestiary {
+	pub kills: Vec<(String, u32)>,
+	pub seen: Vec<String>,
+	pub chatted: Vec<String>,
+}
+
+/// Journey mode powers that are saved with the world
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CreativePowers {
+	pub freeze_time: bool,
+	pub time_speed: f32, // Slider position (0 to 1)
+	pub freeze_rain: bool,
+	pub freeze_wind: bool,
+	pub difficulty: f32, // Slider position (0 to 1)
+	pub freeze_biome_spread: bool,
+}